@@ -0,0 +1,192 @@
+// Access control in front of the protocol handlers.
+// Each incoming connection looks up the remote endpoint in the id store
+// and the protocol's policy says what that status gets:
+// allow , limit (a couple of connections at a time) or refuse.
+// Ids the store has never heard of use the `unknown` row.
+// Connections that get in are recorded as a sighting with the address the
+// endpoint reached it on , so a stranger is Seen after the first one.
+// The policy always sees the status from before the connection.
+// The policy table lives in the node config under [access].
+
+use std::{fmt, sync::Arc};
+
+use dashmap::DashMap;
+use iroh::{
+    Endpoint, EndpointAddr, EndpointId,
+    endpoint::Connection,
+    protocol::{AcceptError, ProtocolHandler},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::id_store::{IdClient, Status};
+
+// open connections per endpoint when limited
+const LIMIT: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Allow,
+    Limit,
+    Refuse,
+}
+
+// One row per status
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    pub unknown: Access,
+    pub seen: Access,
+    pub known: Access,
+    pub apparent: Access,
+    pub fren: Access,
+    pub enemy: Access,
+    pub destroy_on_sight: Access,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            unknown: Access::Allow,
+            seen: Access::Allow,
+            known: Access::Allow,
+            apparent: Access::Allow,
+            fren: Access::Allow,
+            enemy: Access::Refuse,
+            destroy_on_sight: Access::Refuse,
+        }
+    }
+}
+
+impl Policy {
+    // strangers get to ask , but not hog the node
+    pub fn limited() -> Self {
+        Self {
+            unknown: Access::Limit,
+            seen: Access::Limit,
+            ..Self::default()
+        }
+    }
+
+    pub fn access(&self, status: Option<&Status>) -> Access {
+        match status {
+            None => self.unknown,
+            Some(Status::Seen) => self.seen,
+            Some(Status::Known) => self.known,
+            Some(Status::Apparent) => self.apparent,
+            Some(Status::Fren) => self.fren,
+            Some(Status::Enemy) => self.enemy,
+            Some(Status::DestroyOnSight) => self.destroy_on_sight,
+        }
+    }
+}
+
+// Where the endpoint reaches a peer right now , direct addresses and relay
+pub fn remote_addr(endpoint: &Endpoint, remote: EndpointId) -> Option<EndpointAddr> {
+    endpoint.remote_info(remote).map(EndpointAddr::from)
+}
+
+// A protocol handler behind a policy
+pub struct Guarded<P> {
+    name: &'static str,
+    inner: P,
+    policy: Policy,
+    ids: IdClient,
+    endpoint: Endpoint,
+    open: Arc<DashMap<EndpointId, usize>>,
+}
+
+impl<P> Guarded<P> {
+    pub fn new(
+        name: &'static str,
+        inner: P,
+        policy: Policy,
+        ids: IdClient,
+        endpoint: Endpoint,
+    ) -> Self {
+        Self {
+            name,
+            inner,
+            policy,
+            ids,
+            endpoint,
+            open: Arc::new(DashMap::new()),
+        }
+    }
+
+    // false when the endpoint is at the limit already
+    fn take_slot(&self, remote: EndpointId) -> bool {
+        let mut count = self.open.entry(remote).or_insert(0);
+        if *count >= LIMIT {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    fn give_slot(&self, remote: EndpointId) {
+        self.open.remove_if_mut(&remote, |_, count| {
+            *count = count.saturating_sub(1);
+            *count == 0
+        });
+    }
+    // Only for peers that got in , refused ones don't cost a write
+    async fn sighting(&self, remote: EndpointId) {
+        let addr = remote_addr(&self.endpoint, remote);
+        if let Err(e) = self.ids.connected(remote, addr, self.name).await {
+            warn!("{} sighting for {} failed {:#}", self.name, remote, e);
+        }
+    }
+}
+
+impl<P> fmt::Debug for Guarded<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Guarded")
+            .field("name", &self.name)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl<P: ProtocolHandler> ProtocolHandler for Guarded<P> {
+    async fn accept(&self, conn: Connection) -> Result<(), AcceptError> {
+        let remote = conn.remote_id();
+        // the status from before this connection , a broken record is
+        // treated like a stranger
+        let status = match self.ids.get(remote).await {
+            Ok(fren) => fren.map(|fren| fren.status().clone()),
+            Err(e) => {
+                warn!("{} status lookup for {} failed {:#}", self.name, remote, e);
+                None
+            }
+        };
+        match self.policy.access(status.as_ref()) {
+            Access::Allow => {
+                self.sighting(remote).await;
+                self.inner.accept(conn).await
+            }
+            Access::Refuse => {
+                warn!("{} refused {} ({:?})", self.name, remote, status);
+                conn.close(1u32.into(), b"refused");
+                Ok(())
+            }
+            Access::Limit => {
+                if !self.take_slot(remote) {
+                    warn!("{} refused {} , over the limit ({:?})", self.name, remote, status);
+                    conn.close(2u32.into(), b"too many connections");
+                    return Ok(());
+                }
+                debug!("{} limited {} ({:?})", self.name, remote, status);
+                self.sighting(remote).await;
+                let res = self.inner.accept(conn).await;
+                self.give_slot(remote);
+                res
+            }
+        }
+    }
+
+    async fn shutdown(&self) {
+        self.inner.shutdown().await
+    }
+}
@@ -0,0 +1,235 @@
+// Offline admin commands.
+// Work straight on config.db , id.rdb , the blob store tags and the docs
+// store without bringing the network up , so the node must be stopped.
+// Everything prints json for scripts.
+
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::{Context, Result};
+use iroh::EndpointId;
+use iroh_blobs::store::fs::FsStore;
+use iroh_docs::{AuthorId, NamespaceId};
+use n0_future::StreamExt;
+use serde::Serialize;
+
+use crate::cli::{AuthorsCommand, Command, ConfigCommand, DocsCommand, NodesCommand, TagsCommand};
+use crate::config::{ConfigClient, KeyTable};
+use crate::id_store::{IdentityApi, Status};
+use crate::trust;
+use crate::identity::docs_store;
+use crate::node_config::NodeConfig;
+
+#[derive(Serialize)]
+struct TagInfo {
+    name: String,
+    hash: String,
+    format: String,
+}
+
+#[derive(Serialize)]
+struct DocInfo {
+    // from the config doc pointers
+    name: Option<String>,
+    id: String,
+    capability: String,
+}
+
+#[derive(Serialize)]
+struct AuthorInfo {
+    name: Option<String>,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct ConfigInfo<'a> {
+    // none until the node has run once
+    endpoint: Option<String>,
+    secrets_locked: bool,
+    settings: HashMap<String, String>,
+    node_config: &'a NodeConfig,
+}
+
+#[derive(Serialize)]
+struct VouchInfo {
+    voucher: String,
+    subject: String,
+    trusted: bool,
+    timestamp: i64,
+}
+
+#[derive(Serialize)]
+struct ProfileInfo {
+    id: String,
+    nickname: String,
+    avatar: Option<String>,
+    contact: Option<String>,
+    capabilities: Vec<String>,
+    timestamp: i64,
+}
+
+#[derive(Serialize)]
+struct Done {
+    ok: bool,
+}
+
+// None when the command isn't an admin one
+pub async fn run(command: &Command, conf: &ConfigClient, node_conf: &NodeConfig) -> Option<Result<()>> {
+    let res = match command {
+        Command::Nodes { command } => nodes(command, conf, node_conf).await,
+        Command::Tags { command } => tags(command, node_conf).await,
+        Command::Docs { command } => match command {
+            DocsCommand::List => docs(conf, node_conf).await,
+        },
+        Command::Authors { command } => match command {
+            AuthorsCommand::List => authors(conf, node_conf).await,
+        },
+        Command::Config { command } => match command {
+            ConfigCommand::Show => config(conf, node_conf).await,
+        },
+        _ => return None,
+    };
+    Some(res)
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+async fn nodes(command: &NodesCommand, conf: &ConfigClient, node_conf: &NodeConfig) -> Result<()> {
+    let ids = IdentityApi::spawn(&node_conf.path("id.rdb"))?.client();
+    match command {
+        NodesCommand::List => print_json(&ids.list().await?),
+        NodesCommand::SetStatus { id, status } => {
+            let id = EndpointId::from_str(id).context("bad endpoint id")?;
+            let status = Status::from_str(status)?;
+            print_json(&ids.set_status(id, status).await?)
+        }
+        NodesCommand::Vouch { id, revoke } => {
+            let id = EndpointId::from_str(id).context("bad endpoint id")?;
+            let secret = conf.get_secret_key().await?;
+            let vouch = trust::sign(&secret, id, !revoke)?;
+            ids.add_vouch(vouch).await?;
+            let status = match revoke {
+                true => Status::Known,
+                false => Status::Fren,
+            };
+            print_json(&ids.set_status(id, status).await?)
+        }
+        NodesCommand::Vouches => {
+            let list: Vec<VouchInfo> = ids
+                .list_vouches()
+                .await?
+                .into_iter()
+                .map(|v| VouchInfo {
+                    voucher: v.voucher.to_string(),
+                    subject: v.subject.to_string(),
+                    trusted: v.trusted,
+                    timestamp: v.timestamp,
+                })
+                .collect();
+            print_json(&list)
+        }
+        NodesCommand::Profiles => {
+            let list: Vec<ProfileInfo> = ids
+                .list_profiles()
+                .await?
+                .into_iter()
+                .map(|p| ProfileInfo {
+                    id: p.id.to_string(),
+                    nickname: p.nickname,
+                    avatar: p.avatar.map(|hash| hash.to_hex().to_string()),
+                    contact: p.contact,
+                    capabilities: p.capabilities,
+                    timestamp: p.timestamp,
+                })
+                .collect();
+            print_json(&list)
+        }
+    }
+}
+
+async fn tags(command: &TagsCommand, node_conf: &NodeConfig) -> Result<()> {
+    let store = FsStore::load(node_conf.path("blobs")).await?;
+    let tags = store.tags();
+    let res = match command {
+        TagsCommand::List { prefix } => {
+            let mut tag_scan = match prefix {
+                Some(prefix) => tags.list_prefix(prefix.as_str()).await?,
+                None => tags.list().await?,
+            };
+            let mut list = Vec::new();
+            while let Some(tag) = tag_scan.next().await {
+                let tag = tag?;
+                list.push(TagInfo {
+                    name: String::from_utf8_lossy(&tag.name.0).to_string(),
+                    hash: tag.hash.to_hex().to_string(),
+                    format: format!("{:?}", tag.format),
+                });
+            }
+            print_json(&list)
+        }
+        TagsCommand::Rm { name } => {
+            tags.delete(name.as_str()).await?;
+            print_json(&Done { ok: true })
+        }
+        TagsCommand::Rename { from, to } => {
+            tags.rename(from.as_str(), to.as_str()).await?;
+            print_json(&Done { ok: true })
+        }
+    };
+    store.shutdown().await?;
+    res
+}
+
+async fn docs(conf: &ConfigClient, node_conf: &NodeConfig) -> Result<()> {
+    let names: HashMap<NamespaceId, String> = conf
+        .list_keys(KeyTable::Docs)
+        .await?
+        .into_iter()
+        .map(|(name, id)| (NamespaceId::from(id), name))
+        .collect();
+    let mut store = docs_store(&node_conf.data_dir)?;
+    let mut list = Vec::new();
+    for item in store.list_namespaces()? {
+        let (id, capability) = item?;
+        list.push(DocInfo {
+            name: names.get(&id).cloned(),
+            id: id.to_string(),
+            capability: format!("{:?}", capability),
+        });
+    }
+    print_json(&list)
+}
+
+async fn authors(conf: &ConfigClient, node_conf: &NodeConfig) -> Result<()> {
+    let names: HashMap<AuthorId, String> = conf
+        .list_keys(KeyTable::Authors)
+        .await?
+        .into_iter()
+        .map(|(name, id)| (AuthorId::from(id), name))
+        .collect();
+    let mut store = docs_store(&node_conf.data_dir)?;
+    let mut list = Vec::new();
+    for id in store.list_authors()? {
+        let id = id?;
+        list.push(AuthorInfo {
+            name: names.get(&id).cloned(),
+            id: id.to_string(),
+        });
+    }
+    print_json(&list)
+}
+
+async fn config(conf: &ConfigClient, node_conf: &NodeConfig) -> Result<()> {
+    let info = ConfigInfo {
+        endpoint: conf
+            .find_secret_key()
+            .await?
+            .map(|secret| secret.public().to_string()),
+        secrets_locked: conf.is_locked().await?,
+        settings: conf.list_settings().await?.into_iter().collect(),
+        node_config: node_conf,
+    };
+    print_json(&info)
+}
@@ -0,0 +1,484 @@
+// Signed announcements over gossip.
+// Same signed message shape as the finder (stolen from chat). Anything
+// that doesn't decode or verify is dropped , the topic is shared with
+// other traffic.
+//
+// Rotation: after `liminal rotate` the old key has signed the new endpoint
+// id and the new key has signed the move as well , so nobody can move trust
+// onto an id they don't hold. The node rebroadcasts it for ROTATION_WINDOW
+// so peers that were offline at the time still get to move their trust
+// across , older ones are dropped. Each old id moves once (see id_store).
+//
+// Vouches: the ones this node signed are rebroadcast the same way , the
+// ones heard from trusted peers are stored and trust is recomputed a little
+// later , once per burst (see trust.rs).
+//
+// Profiles: a nickname , avatar blob , contact note and what the node runs.
+// Built from the name , avatar and contact settings and signed fresh on each
+// broadcast , so changes in the admin page go out on the next round.
+// Nothing goes out until the node has a name.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use data_encoding::BASE32_NOPAD;
+use iroh::{Endpoint, EndpointId, PublicKey, SecretKey, Signature};
+use iroh_blobs::Hash;
+use iroh_gossip::{
+    api::{Event, GossipSender},
+    net::Gossip,
+    proto::TopicId,
+};
+use n0_future::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::task;
+use tokio::time::{Instant, sleep_until};
+use tracing::{debug, info, warn};
+
+use crate::access;
+use crate::config::ConfigClient;
+use crate::id_store::{IdClient, Profile, Vouch};
+use crate::trust::{self, TrustConfig};
+
+// config setting holding the signed rotation , base32
+pub const ROTATION_SETTING: &str = "rotation";
+
+// settings the profile is made from
+pub const NAME_SETTING: &str = "name";
+pub const AVATAR_SETTING: &str = "avatar";
+pub const CONTACT_SETTING: &str = "contact";
+
+const REPEAT: Duration = Duration::from_secs(3600);
+// rotations are rebroadcast and accepted for this long
+const ROTATION_WINDOW: Duration = Duration::from_secs(30 * 24 * 3600);
+// how far ahead a peer's clock can be
+const CLOCK_SKEW: i64 = 300;
+// wait after a new vouch so a burst gets one recompute
+const SETTLE: Duration = Duration::from_secs(30);
+
+// anything longer is dropped
+const MAX_NICKNAME: usize = 64;
+const MAX_CONTACT: usize = 512;
+const MAX_CAPABILITIES: usize = 32;
+const MAX_CAPABILITY: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Announcement {
+    // the signer has moved to a new endpoint id , proof is the new key
+    // signing rotation_proof
+    Rotate {
+        new: EndpointId,
+        timestamp: i64,
+        proof: Signature,
+    },
+    // the signer trusts (or no longer trusts) the subject
+    Vouch {
+        subject: EndpointId,
+        trusted: bool,
+        timestamp: i64,
+    },
+    // about the signer
+    Profile {
+        nickname: String,
+        avatar: Option<Hash>,
+        contact: Option<String>,
+        capabilities: Vec<String>,
+        timestamp: i64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedAnnouncement {
+    from: PublicKey,
+    data: Bytes,
+    signature: Signature,
+}
+
+impl SignedAnnouncement {
+    pub fn verify_and_decode(bytes: &[u8]) -> Result<(PublicKey, Announcement)> {
+        let signed: Self = postcard::from_bytes(bytes)?;
+        signed
+            .from
+            .verify(&signed.data, &signed.signature)
+            .context("bad signature")?;
+        let message: Announcement = postcard::from_bytes(&signed.data)?;
+        Ok((signed.from, message))
+    }
+
+    pub fn sign_and_encode(secret_key: &SecretKey, message: &Announcement) -> Result<Bytes> {
+        let data: Bytes = postcard::to_stdvec(message)?.into();
+        let signature = secret_key.sign(&data);
+        let signed = Self {
+            from: secret_key.public(),
+            data,
+            signature,
+        };
+        Ok(postcard::to_stdvec(&signed)?.into())
+    }
+}
+
+// Both keys sign the move from old to new , kept in config for rebroadcast
+pub async fn store_rotation(conf: &ConfigClient, old: &SecretKey, new: &SecretKey) -> Result<()> {
+    let timestamp = chrono::Utc::now().timestamp();
+    let rotate = Announcement::Rotate {
+        new: new.public(),
+        timestamp,
+        proof: new.sign(&rotation_proof(&old.public(), &new.public(), timestamp)),
+    };
+    let bytes = SignedAnnouncement::sign_and_encode(old, &rotate)?;
+    conf.set_setting(ROTATION_SETTING, &BASE32_NOPAD.encode(&bytes))
+        .await
+}
+
+// What the new key signs
+fn rotation_proof(old: &EndpointId, new: &EndpointId, timestamp: i64) -> Vec<u8> {
+    let mut data = b"liminal rotate".to_vec();
+    data.extend_from_slice(old.as_bytes());
+    data.extend_from_slice(new.as_bytes());
+    data.extend_from_slice(&timestamp.to_be_bytes());
+    data
+}
+
+// The new key has to agree and the rotation has to be recent
+fn check_rotation(
+    from: &EndpointId,
+    new: &EndpointId,
+    timestamp: i64,
+    proof: &Signature,
+    now: i64,
+) -> Result<()> {
+    if from == new {
+        bail!("rotation to itself");
+    }
+    new.verify(&rotation_proof(from, new, timestamp), proof)
+        .context("rotation is not signed by the new key")?;
+    if timestamp > now + CLOCK_SKEW {
+        bail!("rotation from the future");
+    }
+    if timestamp < now - ROTATION_WINDOW.as_secs() as i64 {
+        bail!("stale rotation");
+    }
+    Ok(())
+}
+
+pub struct Announcer {
+    gossip: Gossip,
+    // for the addresses of new neighbors
+    endpoint: Endpoint,
+    topic: TopicId,
+    // the endpoint key , signs our profile
+    secret: SecretKey,
+    conf: ConfigClient,
+    ids: IdClient,
+    trust: TrustConfig,
+    // protocols this node runs , for the profile
+    capabilities: Vec<String>,
+}
+
+impl Announcer {
+    pub fn new(
+        gossip: Gossip,
+        endpoint: Endpoint,
+        topic: TopicId,
+        secret: SecretKey,
+        conf: ConfigClient,
+        ids: IdClient,
+        trust: TrustConfig,
+        capabilities: Vec<String>,
+    ) -> Self {
+        Self {
+            gossip,
+            endpoint,
+            topic,
+            secret,
+            conf,
+            ids,
+            trust,
+            capabilities,
+        }
+    }
+
+    pub async fn spawn(self) -> Result<()> {
+        let topic = self.gossip.subscribe(self.topic, vec![]).await?;
+        let (tx, mut rx) = topic.split();
+        let Self {
+            endpoint,
+            secret,
+            conf,
+            ids,
+            trust,
+            capabilities,
+            ..
+        } = self;
+        let me = secret.public();
+        task::spawn(async move {
+            let mut ticker = tokio::time::interval(REPEAT);
+            // whose vouches get kept , refreshed with each recompute
+            let mut trusted = recompute(&ids, me, &trust, HashSet::from([me])).await;
+            let mut due: Option<Instant> = None;
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => broadcast(&tx, &secret, &conf, &ids, &capabilities).await,
+                    _ = sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                        due = None;
+                        trusted = recompute(&ids, me, &trust, trusted).await;
+                    }
+                    event = rx.try_next() => match event {
+                        Ok(Some(Event::NeighborUp(neighbor))) => {
+                            let addr = access::remote_addr(&endpoint, neighbor);
+                            if let Err(e) = ids.seen(neighbor, addr, "gossip").await {
+                                warn!("sighting for {} failed {:#}", neighbor, e);
+                            }
+                            broadcast(&tx, &secret, &conf, &ids, &capabilities).await
+                        }
+                        Ok(Some(Event::Received(message))) => {
+                            match receive(&ids, me, &trusted, &message.content).await {
+                                Ok(true) => {
+                                    due.get_or_insert_with(|| Instant::now() + SETTLE);
+                                }
+                                Ok(false) => {}
+                                Err(e) => warn!(
+                                    "announcement from {} failed {:#}",
+                                    message.delivered_from, e
+                                ),
+                            }
+                        }
+                        Ok(Some(_)) => {}
+                        Ok(None) => break,
+                        Err(e) => {
+                            warn!("announce gossip failed {:?}", e);
+                            break;
+                        }
+                    },
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+// Everything this node has to say
+async fn broadcast(
+    tx: &GossipSender,
+    secret: &SecretKey,
+    conf: &ConfigClient,
+    ids: &IdClient,
+    capabilities: &[String],
+) {
+    match profile(secret, conf, capabilities).await {
+        Ok(Some(bytes)) => {
+            if let Err(e) = tx.broadcast(bytes).await {
+                warn!("profile broadcast failed {:?}", e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("can't make profile {:#}", e),
+    }
+    if let Ok(Some(rotation)) = conf.get_setting(ROTATION_SETTING).await {
+        match stored_rotation(&rotation) {
+            // past the window peers drop it anyway
+            Ok((bytes, timestamp)) => {
+                let cutoff = chrono::Utc::now().timestamp() - ROTATION_WINDOW.as_secs() as i64;
+                if timestamp >= cutoff {
+                    if let Err(e) = tx.broadcast(bytes.into()).await {
+                        warn!("rotation broadcast failed {:?}", e);
+                    }
+                }
+            }
+            Err(e) => warn!("stored rotation is bad {:#}", e),
+        }
+    }
+    let vouches = match ids.list_vouches().await {
+        Ok(vouches) => vouches,
+        Err(e) => {
+            warn!("can't list vouches {:#}", e);
+            return;
+        }
+    };
+    for vouch in vouches.into_iter().filter(|v| v.voucher == secret.public()) {
+        if let Err(e) = tx.broadcast(vouch.signed.into()).await {
+            warn!("vouch broadcast failed {:?}", e);
+        }
+    }
+}
+
+// The signed rotation and when it was made
+fn stored_rotation(setting: &str) -> Result<(Vec<u8>, i64)> {
+    let bytes = BASE32_NOPAD.decode(setting.as_bytes())?;
+    match SignedAnnouncement::verify_and_decode(&bytes)? {
+        (_, Announcement::Rotate { timestamp, .. }) => Ok((bytes, timestamp)),
+        _ => bail!("not a rotation"),
+    }
+}
+
+// None until the node has a name
+async fn profile(secret: &SecretKey, conf: &ConfigClient, capabilities: &[String]) -> Result<Option<Bytes>> {
+    let Some(nickname) = conf.get_setting(NAME_SETTING).await? else {
+        return Ok(None);
+    };
+    let avatar = match conf.get_setting(AVATAR_SETTING).await? {
+        Some(hash) => Some(hash.parse::<Hash>().context("avatar setting is not a blob hash")?),
+        None => None,
+    };
+    let announcement = Announcement::Profile {
+        nickname,
+        avatar,
+        contact: conf.get_setting(CONTACT_SETTING).await?,
+        capabilities: capabilities.to_vec(),
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+    Ok(Some(SignedAnnouncement::sign_and_encode(secret, &announcement)?))
+}
+
+// A failed recompute keeps the set it had
+async fn recompute(
+    ids: &IdClient,
+    me: EndpointId,
+    trust: &TrustConfig,
+    current: HashSet<EndpointId>,
+) -> HashSet<EndpointId> {
+    match trust::apply(ids, me, trust).await {
+        Ok(trusted) => trusted,
+        Err(e) => {
+            warn!("trust recompute failed {:#}", e);
+            current
+        }
+    }
+}
+
+// true when trust wants recomputing , a new vouch or a rotation
+async fn receive(
+    ids: &IdClient,
+    me: EndpointId,
+    vouchers: &HashSet<EndpointId>,
+    content: &[u8],
+) -> Result<bool> {
+    // not ours , someone else on the topic
+    let Ok((from, announcement)) = SignedAnnouncement::verify_and_decode(content) else {
+        return Ok(false);
+    };
+    match announcement {
+        Announcement::Rotate {
+            new,
+            timestamp,
+            proof,
+        } => {
+            let now = chrono::Utc::now().timestamp();
+            check_rotation(&from, &new, timestamp, &proof, now)?;
+            // trust moved across , the trusted set changes with it
+            if ids.rotate(from, new, timestamp).await? {
+                info!("{} rotated to {}", from, new);
+                return Ok(true);
+            }
+        }
+        Announcement::Vouch {
+            subject,
+            trusted,
+            timestamp,
+        } => {
+            if from == subject {
+                bail!("vouch for itself");
+            }
+            // ours come from the cli , an echo changes nothing
+            if from == me {
+                return Ok(false);
+            }
+            // strangers can't fill the store , they're heard again
+            // on the next rebroadcast if they become trusted
+            if !vouchers.contains(&from) {
+                debug!("vouch from untrusted {} dropped", from);
+                return Ok(false);
+            }
+            let vouch = Vouch {
+                voucher: from,
+                subject,
+                trusted,
+                timestamp,
+                signed: content.to_vec(),
+            };
+            if ids.add_vouch(vouch).await? {
+                info!("{} vouched {} for {}", from, trusted, subject);
+                return Ok(true);
+            }
+        }
+        Announcement::Profile {
+            nickname,
+            avatar,
+            contact,
+            capabilities,
+            timestamp,
+        } => {
+            if from == me {
+                return Ok(false);
+            }
+            let nickname = nickname.trim().to_string();
+            if nickname.is_empty() || nickname.chars().count() > MAX_NICKNAME {
+                bail!("bad nickname");
+            }
+            if contact.as_ref().is_some_and(|c| c.chars().count() > MAX_CONTACT) {
+                bail!("contact too long");
+            }
+            if capabilities.len() > MAX_CAPABILITIES {
+                bail!("too many capabilities");
+            }
+            if capabilities.iter().any(|c| c.chars().count() > MAX_CAPABILITY) {
+                bail!("capability too long");
+            }
+            let profile = Profile {
+                id: from,
+                nickname,
+                avatar,
+                contact,
+                capabilities,
+                timestamp,
+                signed: content.to_vec(),
+            };
+            let nickname = profile.nickname.clone();
+            if ids.add_profile(profile).await? {
+                debug!("{} is {}", from, nickname);
+            }
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn key(n: u8) -> SecretKey {
+        SecretKey::from_bytes(&[n; 32])
+    }
+
+    fn proof(signer: &SecretKey, old: &SecretKey, new: &SecretKey, timestamp: i64) -> Signature {
+        signer.sign(&rotation_proof(&old.public(), &new.public(), timestamp))
+    }
+
+    #[test]
+    fn rotations_need_the_new_key() {
+        let (old, new) = (key(1), key(2));
+        let good = proof(&new, &old, &new, NOW);
+        assert!(check_rotation(&old.public(), &new.public(), NOW, &good, NOW).is_ok());
+        // the old key alone can't point at someone else's id
+        let forged = proof(&old, &old, &new, NOW);
+        assert!(check_rotation(&old.public(), &new.public(), NOW, &forged, NOW).is_err());
+        // a proof is for one move only
+        let other = key(3);
+        assert!(check_rotation(&other.public(), &new.public(), NOW, &good, NOW).is_err());
+    }
+
+    #[test]
+    fn rotations_must_be_recent() {
+        let (old, new) = (key(1), key(2));
+        let stale = NOW - ROTATION_WINDOW.as_secs() as i64 - 1;
+        let signed = proof(&new, &old, &new, stale);
+        assert!(check_rotation(&old.public(), &new.public(), stale, &signed, NOW).is_err());
+        let ahead = NOW + CLOCK_SKEW + 1;
+        let signed = proof(&new, &old, &new, ahead);
+        assert!(check_rotation(&old.public(), &new.public(), ahead, &signed, NOW).is_err());
+    }
+}
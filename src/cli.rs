@@ -1,6 +1,6 @@
-use std::{fmt, str::FromStr};
+use std::{fmt, path::PathBuf, str::FromStr};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 // use iroh::NodeAddr;
 
 use iroh::EndpointAddr;
@@ -18,12 +18,45 @@ pub struct Args {
     /// Do a full replica of all the data 
     #[clap(short, long)]
     pub duplicate: bool,
+    /// Config file, defaults to liminal.toml when it exists.
+    #[clap(short, long, env = "LIMINAL_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Where the node keeps its databases and blobs.
+    #[clap(long, env = "LIMINAL_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
     /// Set your nickname.
-    #[clap(short, long)]
+    #[clap(short, long, env = "LIMINAL_NAME")]
     pub name: Option<String>,
     /// Set the bind port for our socket. By default, a random port will be used.
-    #[clap(short, long, default_value = "0")]
-    pub bind_port: u16,
+    #[clap(short, long, env = "LIMINAL_BIND_PORT")]
+    pub bind_port: Option<u16>,
+    /// Address the web interface listens on.
+    #[clap(long, env = "LIMINAL_WEB_ADDRESS")]
+    pub web_address: Option<String>,
+    /// Port the web interface listens on.
+    #[clap(long, env = "LIMINAL_WEB_PORT")]
+    pub web_port: Option<u16>,
+    /// Relay to use: default, staging, disabled or a relay url.
+    #[clap(long, env = "LIMINAL_RELAY")]
+    pub relay: Option<String>,
+    /// Gossip topic name for replication.
+    #[clap(long, env = "LIMINAL_TOPIC")]
+    pub topic: Option<String>,
+    /// Ask for the passphrase when the secrets are locked.
+    #[clap(long)]
+    pub unlock: bool,
+    /// Keyfile for locked secrets , for running as a service.
+    #[clap(long, env = "LIMINAL_KEYFILE")]
+    pub keyfile: Option<PathBuf>,
+    /// Tag prefixes to replicate, repeat or comma separate.
+    #[clap(long, env = "LIMINAL_PREFIXES", value_delimiter = ',')]
+    pub prefix: Vec<String>,
+    /// Days a purged note stays in the trash before it is really deleted.
+    #[clap(long, env = "LIMINAL_TRASH_DAYS")]
+    pub trash_days: Option<u64>,
+    /// Hours between notes snapshots, 0 turns them off.
+    #[clap(long, env = "LIMINAL_SNAPSHOT_HOURS")]
+    pub snapshot_hours: Option<u64>,
     #[clap(subcommand)]
     pub command: Command,
 }
@@ -36,7 +69,89 @@ pub enum Command {
         /// The ticket, as base32 string
         /// Just a node , assumes that it has gossip and is listening for "liminal::"
         ticket: String,
-    }
+    },
+    /// Write the node key and docs authors to a passphrase locked bundle.
+    Export {
+        file: PathBuf,
+    },
+    /// Load a bundle made with export , the node takes on that identity.
+    Import {
+        file: PathBuf,
+    },
+    /// Make a new node key , peers are told with a message signed by the old one.
+    Rotate,
+    /// Encrypt the stored secrets with a passphrase , or --keyfile.
+    LockSecrets,
+    /// Store the secrets in the clear again.
+    UnlockSecrets,
+    /// Nodes in the id store.
+    Nodes {
+        #[clap(subcommand)]
+        command: NodesCommand,
+    },
+    /// Blob store tags.
+    Tags {
+        #[clap(subcommand)]
+        command: TagsCommand,
+    },
+    /// Docs in the docs store.
+    Docs {
+        #[clap(subcommand)]
+        command: DocsCommand,
+    },
+    /// Docs authors.
+    Authors {
+        #[clap(subcommand)]
+        command: AuthorsCommand,
+    },
+    /// Node configuration.
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+// Offline admin commands , all print json
+
+#[derive(Subcommand, Debug)]
+pub enum NodesCommand {
+    List,
+    /// seen, known, apparent, fren, enemy or destroy-on-sight
+    SetStatus { id: String, status: String },
+    /// Sign a vouch for a peer and mark it fren , peers hear it while the node runs
+    Vouch {
+        id: String,
+        /// take a vouch back , the peer drops to known
+        #[clap(long)]
+        revoke: bool,
+    },
+    /// Vouches stored in the id store.
+    Vouches,
+    /// Profiles peers have sent.
+    Profiles,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TagsCommand {
+    List { prefix: Option<String> },
+    Rm { name: String },
+    Rename { from: String, to: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DocsCommand {
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AuthorsCommand {
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// The merged config , settings and endpoint id.
+    Show,
 }
 
 // Base ticket join ( just node address for now)
@@ -2,30 +2,53 @@
 //! Stores secret key and some peers for now.
 //! The layout for this is stolen from the persistant store
 //! in iroh-blobs. Seemed like a good layout.
+//!
+//! The database sits behind an irpc actor (same shape as id_store)
+//! so a cloneable client can go into rocket and the admin page.
+//!
+//! Secrets can be locked with a passphrase or keyfile. Then they live
+//! sealed in the locked table and the actor holds the key once unlocked ,
+//! the plain secrets table is left empty.
 
-use anyhow::{Result, anyhow};
+use std::{fmt, fs, path::PathBuf};
+
+use anyhow::{Context, Result, anyhow, ensure};
 use iroh::{EndpointId, PublicKey, SecretKey};
-use redb::{Database, ReadableDatabase, Table, TableDefinition, WriteTransaction};
-use std::{fs, path::PathBuf};
+use irpc::{Client, WithChannels, channel::oneshot, rpc_requests};
+use redb::{Database, ReadableDatabase, ReadableTable, Table, TableDefinition, WriteTransaction};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+
+use crate::passphrase;
+use crate::schema::{self, Migration};
 
 const TIME_TABLE: TableDefinition<&str, u64> = TableDefinition::new("timings");
 const NODE_TABLE: TableDefinition<&[u8; 32], &str> = TableDefinition::new("nodes");
 const SECRET_TABLE: TableDefinition<u32, &[u8; 32]> = TableDefinition::new("secrets");
 const DOCS_TABLE: TableDefinition<&str, &[u8; 32]> = TableDefinition::new("doc_pointers");
 const AUTHORS_TABLE: TableDefinition<&str, &[u8; 32]> = TableDefinition::new("authors");
+const SETTINGS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("settings");
+const LOCKED_TABLE: TableDefinition<u32, &[u8]> = TableDefinition::new("locked_secrets");
+// salt and check value for locked secrets
+const LOCK_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("secret_lock");
 
-pub struct Info {
-    db: Database,
-    current: CurrentTransaction,
-}
+const LOCK_CHECK: &[u8] = b"liminal secrets unlocked";
 
-#[derive(Default)]
-enum CurrentTransaction {
-    #[default]
-    None,
-    Read,
-    Write,
-}
+// Add new ones on the end
+const MIGRATIONS: &[Migration] = &[Migration {
+    to: 1,
+    about: "create tables",
+    run: |tx| {
+        Tables::new(tx)?;
+        Ok(())
+    },
+}];
+
+// Slots in the secrets table
+const NODE_SECRET: u32 = 0;
+const ROCKET_SECRET: u32 = 1;
+// node secret from before the last rotation or import
+const PREVIOUS_SECRET: u32 = 2;
 
 pub struct Tables<'tx> {
     pub timing: Table<'tx, &'static str, u64>,
@@ -33,6 +56,9 @@ pub struct Tables<'tx> {
     pub secrets: Table<'tx, u32, &'static [u8; 32]>,
     pub docs: Table<'tx, &'static str, &'static [u8; 32]>,
     pub authors: Table<'tx, &'static str, &'static [u8; 32]>,
+    pub settings: Table<'tx, &'static str, &'static str>,
+    pub locked: Table<'tx, u32, &'static [u8]>,
+    pub lock: Table<'tx, &'static str, &'static [u8]>,
 }
 
 impl<'tx> Tables<'tx> {
@@ -42,6 +68,9 @@ impl<'tx> Tables<'tx> {
         let secrets = tx.open_table(SECRET_TABLE)?;
         let docs = tx.open_table(DOCS_TABLE)?;
         let authors = tx.open_table(AUTHORS_TABLE)?;
+        let settings = tx.open_table(SETTINGS_TABLE)?;
+        let locked = tx.open_table(LOCKED_TABLE)?;
+        let lock = tx.open_table(LOCK_TABLE)?;
 
         Ok(Self {
             timing,
@@ -49,134 +78,657 @@ impl<'tx> Tables<'tx> {
             secrets,
             docs,
             authors,
+            settings,
+            locked,
+            lock,
         })
     }
 }
 
-impl Info {
-    pub fn new(name: &PathBuf) -> Result<Self> {
-        fs::create_dir_all(name.parent().unwrap())?;
-        let db = match Database::create(name) {
-            Ok(database) => database,
-            Err(e) => return Err(anyhow!("bad database create, {}", e)),
-        };
-        let write_tx = db.begin_write()?;
-        let _ = Tables::new(&write_tx)?;
-        write_tx.commit()?;
-        // let read_tx = db.begin_read()?;
-        // for i in read_tx.list_tables()? {
-        //     println!("{:?}", i.name());
-        // }
-        Ok(Self {
-            db: db,
-            current: CurrentTransaction::default(),
-        })
+// Errors come back over the channel as text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<anyhow::Error> for ConfigError {
+    fn from(e: anyhow::Error) -> Self {
+        Self(format!("{e:#}"))
+    }
+}
+
+type Reply<T> = Result<T, ConfigError>;
+
+// Which of the named key tables
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum KeyTable {
+    Docs,
+    Authors,
+}
+
+impl KeyTable {
+    fn definition(&self) -> TableDefinition<'static, &'static str, &'static [u8; 32]> {
+        match self {
+            KeyTable::Docs => DOCS_TABLE,
+            KeyTable::Authors => AUTHORS_TABLE,
+        }
     }
+}
 
-    pub fn add_node(&mut self, node: PublicKey) -> Result<()> {
+// What opens locked secrets
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Unlock {
+    Passphrase(String),
+    // contents of the keyfile
+    Keyfile(Vec<u8>),
+}
+
+// Keep it out of the logs
+impl fmt::Debug for Unlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unlock::Passphrase(_) => f.write_str("Passphrase(..)"),
+            Unlock::Keyfile(_) => f.write_str("Keyfile(..)"),
+        }
+    }
+}
+
+impl Unlock {
+    fn key(&self, salt: &[u8]) -> Result<[u8; 32]> {
+        match self {
+            Unlock::Passphrase(pass) => passphrase::stretch(pass, salt),
+            Unlock::Keyfile(contents) => passphrase::keyfile_key(contents),
+        }
+    }
+}
+
+// Irpc
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Secret {
+    slot: u32,
+    // make one if the slot is empty
+    create: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetSecret {
+    slot: u32,
+    value: [u8; 32],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IsLocked;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenSecrets {
+    with: Unlock,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LockSecrets {
+    with: Unlock,
+}
+
+// back to plain secrets , has to be unlocked first
+#[derive(Debug, Serialize, Deserialize)]
+struct RemoveLock;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AddNode {
+    node: [u8; 32],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ListNodes;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GetKey {
+    table: KeyTable,
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetKey {
+    table: KeyTable,
+    name: String,
+    value: [u8; 32],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ListKeys {
+    table: KeyTable,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GetSetting {
+    name: String,
+}
+
+// None removes the setting
+#[derive(Debug, Serialize, Deserialize)]
+struct SetSetting {
+    name: String,
+    value: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ListSettings;
+
+#[rpc_requests(message = ConfigMessage, no_rpc, no_spans)]
+#[derive(Serialize, Deserialize, Debug)]
+enum ConfigProtocol {
+    #[rpc(tx=oneshot::Sender<Reply<Option<[u8; 32]>>>)]
+    Secret(Secret),
+    #[rpc(tx=oneshot::Sender<Reply<()>>)]
+    SetSecret(SetSecret),
+    #[rpc(tx=oneshot::Sender<Reply<bool>>)]
+    IsLocked(IsLocked),
+    #[rpc(tx=oneshot::Sender<Reply<()>>)]
+    OpenSecrets(OpenSecrets),
+    #[rpc(tx=oneshot::Sender<Reply<()>>)]
+    LockSecrets(LockSecrets),
+    #[rpc(tx=oneshot::Sender<Reply<()>>)]
+    RemoveLock(RemoveLock),
+    #[rpc(tx=oneshot::Sender<Reply<()>>)]
+    AddNode(AddNode),
+    #[rpc(tx=oneshot::Sender<Reply<Vec<([u8; 32], String)>>>)]
+    ListNodes(ListNodes),
+    #[rpc(tx=oneshot::Sender<Reply<Option<[u8; 32]>>>)]
+    GetKey(GetKey),
+    #[rpc(tx=oneshot::Sender<Reply<()>>)]
+    SetKey(SetKey),
+    #[rpc(tx=oneshot::Sender<Reply<Vec<(String, [u8; 32])>>>)]
+    ListKeys(ListKeys),
+    #[rpc(tx=oneshot::Sender<Reply<Option<String>>>)]
+    GetSetting(GetSetting),
+    #[rpc(tx=oneshot::Sender<Reply<()>>)]
+    SetSetting(SetSetting),
+    #[rpc(tx=oneshot::Sender<Reply<Vec<(String, String)>>>)]
+    ListSettings(ListSettings),
+}
+
+struct Actor {
+    recv: tokio::sync::mpsc::Receiver<ConfigMessage>,
+    db: Database,
+    // secrets key once unlocked
+    key: Option<[u8; 32]>,
+}
+
+impl Actor {
+    async fn run(mut self) {
+        while let Some(msg) = self.recv.recv().await {
+            self.handle(msg).await;
+        }
+    }
+
+    async fn handle(&mut self, msg: ConfigMessage) {
+        match msg {
+            ConfigMessage::Secret(secret) => {
+                let WithChannels { tx, inner, .. } = secret;
+                tx.send(self.secret(inner.slot, inner.create).map_err(Into::into))
+                    .await
+                    .ok();
+            }
+            ConfigMessage::SetSecret(set) => {
+                let WithChannels { tx, inner, .. } = set;
+                tx.send(self.set_secret(inner.slot, &inner.value).map_err(Into::into))
+                    .await
+                    .ok();
+            }
+            ConfigMessage::IsLocked(is_locked) => {
+                let WithChannels { tx, .. } = is_locked;
+                tx.send(self.is_locked().map_err(Into::into)).await.ok();
+            }
+            ConfigMessage::OpenSecrets(open) => {
+                let WithChannels { tx, inner, .. } = open;
+                tx.send(self.open_secrets(&inner.with).map_err(Into::into))
+                    .await
+                    .ok();
+            }
+            ConfigMessage::LockSecrets(lock) => {
+                let WithChannels { tx, inner, .. } = lock;
+                tx.send(self.lock_secrets(&inner.with).map_err(Into::into))
+                    .await
+                    .ok();
+            }
+            ConfigMessage::RemoveLock(remove) => {
+                let WithChannels { tx, .. } = remove;
+                tx.send(self.remove_lock().map_err(Into::into)).await.ok();
+            }
+            ConfigMessage::AddNode(add) => {
+                let WithChannels { tx, inner, .. } = add;
+                tx.send(self.add_node(&inner.node).map_err(Into::into)).await.ok();
+            }
+            ConfigMessage::ListNodes(list) => {
+                let WithChannels { tx, .. } = list;
+                tx.send(self.list_nodes().map_err(Into::into)).await.ok();
+            }
+            ConfigMessage::GetKey(get) => {
+                let WithChannels { tx, inner, .. } = get;
+                tx.send(self.get_key(inner.table, &inner.name).map_err(Into::into))
+                    .await
+                    .ok();
+            }
+            ConfigMessage::SetKey(set) => {
+                let WithChannels { tx, inner, .. } = set;
+                tx.send(
+                    self.set_key(inner.table, &inner.name, &inner.value)
+                        .map_err(Into::into),
+                )
+                .await
+                .ok();
+            }
+            ConfigMessage::ListKeys(list) => {
+                let WithChannels { tx, inner, .. } = list;
+                tx.send(self.list_keys(inner.table).map_err(Into::into)).await.ok();
+            }
+            ConfigMessage::GetSetting(get) => {
+                let WithChannels { tx, inner, .. } = get;
+                tx.send(self.get_setting(&inner.name).map_err(Into::into)).await.ok();
+            }
+            ConfigMessage::SetSetting(set) => {
+                let WithChannels { tx, inner, .. } = set;
+                tx.send(
+                    self.set_setting(&inner.name, inner.value.as_deref())
+                        .map_err(Into::into),
+                )
+                .await
+                .ok();
+            }
+            ConfigMessage::ListSettings(list) => {
+                let WithChannels { tx, .. } = list;
+                tx.send(self.list_settings().map_err(Into::into)).await.ok();
+            }
+        }
+    }
+
+    // Secrets are made the first time they are asked for
+    fn secret(&self, slot: u32, create: bool) -> Result<Option<[u8; 32]>> {
+        let read_tx = self.db.begin_read()?;
+        if self.is_locked()? {
+            let key = self.unlocked()?;
+            let locked = read_tx.open_table(LOCKED_TABLE)?;
+            if let Some(data) = locked.get(slot)? {
+                let plain = passphrase::open(&key, data.value(), &slot_aad(slot))?;
+                let secret: [u8; 32] = plain
+                    .try_into()
+                    .map_err(|_| anyhow!("bad secret in slot {slot}"))?;
+                return Ok(Some(secret));
+            }
+        } else {
+            let secrets = read_tx.open_table(SECRET_TABLE)?;
+            if let Some(data) = secrets.get(slot)? {
+                return Ok(Some(*data.value()));
+            }
+        }
+        if !create {
+            return Ok(None);
+        }
+        println!("Make a new secret in slot {slot}");
+        let secret = SecretKey::generate(&mut rand::rng()).to_bytes();
+        self.set_secret(slot, &secret)?;
+        Ok(Some(secret))
+    }
+
+    fn set_secret(&self, slot: u32, value: &[u8; 32]) -> Result<()> {
+        let locked = self.is_locked()?;
         let write_tx = self.db.begin_write()?;
-        {
-            let mut nodes = write_tx.open_table(NODE_TABLE)?;
-            let _ = nodes.insert(node.as_bytes(), "fren")?;
+        if locked {
+            let sealed = passphrase::seal(&self.unlocked()?, value, &slot_aad(slot))?;
+            let mut locked = write_tx.open_table(LOCKED_TABLE)?;
+            locked.insert(slot, sealed.as_slice())?;
+        } else {
+            let mut secrets = write_tx.open_table(SECRET_TABLE)?;
+            secrets.insert(slot, value)?;
         }
         write_tx.commit()?;
         Ok(())
     }
 
-    pub fn list_nodes(&self) -> Result<()> {
+    fn is_locked(&self) -> Result<bool> {
         let read_tx = self.db.begin_read()?;
-        let nodes = read_tx.open_table(NODE_TABLE)?;
-        for (k, v) in nodes.range::<&'static [u8; 32]>(..)?.flatten() {
-            println!("{:?}, {:?}", EndpointId::from_bytes(k.value()), v.value());
-        }
-        Ok(())
+        let lock = read_tx.open_table(LOCK_TABLE)?;
+        Ok(lock.get("check")?.is_some())
     }
 
-    pub fn get_secret_key(&self) -> Result<SecretKey> {
+    fn unlocked(&self) -> Result<[u8; 32]> {
+        self.key
+            .context("secrets are locked , start with --unlock or --keyfile")
+    }
+
+    fn open_secrets(&mut self, with: &Unlock) -> Result<()> {
         let read_tx = self.db.begin_read()?;
-        let secrets = read_tx.open_table(SECRET_TABLE)?;
-        if let Some(data) = secrets.get(0)? {
-            return Ok(SecretKey::from_bytes(data.value()));
-        } else {
-            println!("Make a new secret key");
-            let write_tx = self.db.begin_write()?;
-            let secret = SecretKey::generate(&mut rand::rng());
-            {
-                let mut secrets = write_tx.open_table(SECRET_TABLE)?;
-                secrets.insert(0, &secret.to_bytes())?;
+        let lock = read_tx.open_table(LOCK_TABLE)?;
+        let salt = lock.get("salt")?.context("secrets are not locked")?;
+        let check = lock.get("check")?.context("secrets are not locked")?;
+        let key = with.key(salt.value())?;
+        passphrase::open(&key, check.value(), LOCK_CHECK)
+            .context("wrong passphrase or keyfile")?;
+        self.key = Some(key);
+        Ok(())
+    }
+
+    // Seal everything in the secrets table and empty it
+    fn lock_secrets(&mut self, with: &Unlock) -> Result<()> {
+        ensure!(!self.is_locked()?, "secrets are already locked");
+        let salt: [u8; 16] = rand::random();
+        let key = with.key(&salt)?;
+        let write_tx = self.db.begin_write()?;
+        {
+            let mut secrets = write_tx.open_table(SECRET_TABLE)?;
+            let mut locked = write_tx.open_table(LOCKED_TABLE)?;
+            let mut lock = write_tx.open_table(LOCK_TABLE)?;
+            let mut plain = Vec::new();
+            for item in secrets.iter()? {
+                let (k, v) = item?;
+                plain.push((k.value(), *v.value()));
+            }
+            for (slot, value) in plain {
+                let sealed = passphrase::seal(&key, &value, &slot_aad(slot))?;
+                locked.insert(slot, sealed.as_slice())?;
+                secrets.remove(slot)?;
             }
-            write_tx.commit()?;
-            return Ok(secret);
+            let check = passphrase::seal(&key, LOCK_CHECK, LOCK_CHECK)?;
+            lock.insert("salt", salt.as_slice())?;
+            lock.insert("check", check.as_slice())?;
         }
+        write_tx.commit()?;
+        self.key = Some(key);
+        // Shrinks the file , it does not scrub it. Freed pages can still
+        // hold the clear secrets until redb reuses them , see the README.
+        self.db
+            .compact()
+            .context("secrets are locked but config.db could not be compacted")?;
+        Ok(())
     }
 
-    pub fn rocket_key(&self) -> Result<[u8; 32]> {
-        let read_tx = self.db.begin_read()?;
-        let secrets = read_tx.open_table(SECRET_TABLE)?;
-        if let Some(data) = secrets.get(1)? {
-            return Ok(data.value().clone());
-        } else {
-            println!("Create secret cookie key");
-            let write_tx = self.db.begin_write()?;
-            let secret = SecretKey::generate(&mut rand::rng());
-            {
-                let mut secrets = write_tx.open_table(SECRET_TABLE)?;
-                secrets.insert(1, &secret.to_bytes())?;
+    fn remove_lock(&mut self) -> Result<()> {
+        ensure!(self.is_locked()?, "secrets are not locked");
+        let key = self.unlocked()?;
+        let write_tx = self.db.begin_write()?;
+        {
+            let mut secrets = write_tx.open_table(SECRET_TABLE)?;
+            let mut locked = write_tx.open_table(LOCKED_TABLE)?;
+            let mut lock = write_tx.open_table(LOCK_TABLE)?;
+            let mut sealed = Vec::new();
+            for item in locked.iter()? {
+                let (k, v) = item?;
+                sealed.push((k.value(), v.value().to_vec()));
+            }
+            for (slot, data) in sealed {
+                let plain = passphrase::open(&key, &data, &slot_aad(slot))?;
+                let secret: [u8; 32] = plain
+                    .try_into()
+                    .map_err(|_| anyhow!("bad secret in slot {slot}"))?;
+                secrets.insert(slot, &secret)?;
+                locked.remove(slot)?;
             }
-            write_tx.commit()?;
-            return Ok(secret.to_bytes());
+            lock.remove("salt")?;
+            lock.remove("check")?;
         }
+        write_tx.commit()?;
+        self.key = None;
+        Ok(())
     }
 
-    pub fn get_docs_key(&self, name: &str) -> Result<[u8; 32]> {
+    fn add_node(&self, node: &[u8; 32]) -> Result<()> {
+        let write_tx = self.db.begin_write()?;
+        {
+            let mut nodes = write_tx.open_table(NODE_TABLE)?;
+            nodes.insert(node, "fren")?;
+        }
+        write_tx.commit()?;
+        Ok(())
+    }
+
+    fn list_nodes(&self) -> Result<Vec<([u8; 32], String)>> {
         let read_tx = self.db.begin_read()?;
-        let docs = read_tx.open_table(DOCS_TABLE)?;
-        if let Some(data) = docs.get(name)? {
-            return Ok(data.value().clone());
-        } else {
-            return Err(anyhow!("key does not exist"));
+        let nodes = read_tx.open_table(NODE_TABLE)?;
+        let mut res = Vec::new();
+        for item in nodes.iter()? {
+            let (k, v) = item?;
+            res.push((*k.value(), v.value().to_string()));
         }
+        Ok(res)
     }
 
-    pub fn set_docs_key(&self, name: &str, value: [u8; 32]) -> Result<()> {
+    fn get_key(&self, table: KeyTable, name: &str) -> Result<Option<[u8; 32]>> {
+        let read_tx = self.db.begin_read()?;
+        let keys = read_tx.open_table(table.definition())?;
+        Ok(keys.get(name)?.map(|data| *data.value()))
+    }
+
+    fn set_key(&self, table: KeyTable, name: &str, value: &[u8; 32]) -> Result<()> {
         let write_tx = self.db.begin_write()?;
         {
-            let mut docs = write_tx.open_table(DOCS_TABLE)?;
-            docs.insert(name, &value)?;
+            let mut keys = write_tx.open_table(table.definition())?;
+            keys.insert(name, value)?;
         }
         write_tx.commit()?;
         Ok(())
     }
 
-    pub fn get_notes_id(&self) -> Result<[u8; 32]> {
-        // let key = iroh_docs::NamespacePublicKey::from_str(
-        //     "7c348d28ea5cbe4001bdb21fe9446b6f936b5424a3c1c9712fdda706d1c40181",
-        // )?;
-        // let _ = self.set_docs_key("notes", *key.as_bytes());
-        self.get_docs_key("notes")
+    fn list_keys(&self, table: KeyTable) -> Result<Vec<(String, [u8; 32])>> {
+        let read_tx = self.db.begin_read()?;
+        let keys = read_tx.open_table(table.definition())?;
+        let mut res = Vec::new();
+        for item in keys.iter()? {
+            let (k, v) = item?;
+            res.push((k.value().to_string(), *v.value()));
+        }
+        Ok(res)
     }
 
-    pub fn get_author_key(&self, name: &str) -> Result<[u8; 32]> {
+    fn get_setting(&self, name: &str) -> Result<Option<String>> {
         let read_tx = self.db.begin_read()?;
-        let authors = read_tx.open_table(AUTHORS_TABLE)?;
-        if let Some(data) = authors.get(name)? {
-            return Ok(data.value().clone());
-        } else {
-            return Err(anyhow!("key does not exist"));
-        }
+        let settings = read_tx.open_table(SETTINGS_TABLE)?;
+        Ok(settings.get(name)?.map(|v| v.value().to_string()))
     }
 
-    pub fn set_author_key(&self, name: &str, value: [u8; 32]) -> Result<()> {
+    fn set_setting(&self, name: &str, value: Option<&str>) -> Result<()> {
         let write_tx = self.db.begin_write()?;
         {
-            let mut authors = write_tx.open_table(AUTHORS_TABLE)?;
-            authors.insert(name, &value)?;
+            let mut settings = write_tx.open_table(SETTINGS_TABLE)?;
+            match value {
+                Some(value) => settings.insert(name, value)?,
+                None => settings.remove(name)?,
+            };
         }
         write_tx.commit()?;
         Ok(())
     }
 
-    pub fn get_notes_author(&self) -> Result<[u8; 32]> {
-        self.get_author_key("notes")
+    fn list_settings(&self) -> Result<Vec<(String, String)>> {
+        let read_tx = self.db.begin_read()?;
+        let settings = read_tx.open_table(SETTINGS_TABLE)?;
+        let mut res = Vec::new();
+        for item in settings.iter()? {
+            let (k, v) = item?;
+            res.push((k.value().to_string(), v.value().to_string()));
+        }
+        Ok(res)
+    }
+}
+
+pub struct ConfigApi {
+    tx: Sender<ConfigMessage>,
+}
+
+impl ConfigApi {
+    pub fn spawn(name: &PathBuf) -> Result<ConfigApi> {
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        if let Some(parent) = name.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let db = match Database::create(name) {
+            Ok(database) => database,
+            Err(e) => return Err(anyhow!("bad database create, {}", e)),
+        };
+        schema::migrate(&db, "config.db", MIGRATIONS)?;
+
+        let actor = Actor {
+            recv: rx,
+            db: db,
+            key: None,
+        };
+        n0_future::task::spawn(actor.run());
+
+        Ok(ConfigApi { tx: tx })
+    }
+
+    pub fn client(&self) -> ConfigClient {
+        ConfigClient {
+            inner: Client::local(self.tx.clone()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ConfigClient {
+    inner: Client<ConfigProtocol>,
+}
+
+impl ConfigClient {
+    async fn secret(&self, slot: u32, create: bool) -> Result<Option<[u8; 32]>> {
+        Ok(self.inner.rpc(Secret { slot, create }).await??)
+    }
+
+    async fn set_secret(&self, slot: u32, value: [u8; 32]) -> Result<()> {
+        Ok(self.inner.rpc(SetSecret { slot, value }).await??)
     }
+
+    pub async fn is_locked(&self) -> Result<bool> {
+        Ok(self.inner.rpc(IsLocked).await??)
+    }
+
+    pub async fn open_secrets(&self, with: Unlock) -> Result<()> {
+        Ok(self.inner.rpc(OpenSecrets { with }).await??)
+    }
+
+    pub async fn lock_secrets(&self, with: Unlock) -> Result<()> {
+        Ok(self.inner.rpc(LockSecrets { with }).await??)
+    }
+
+    pub async fn remove_lock(&self) -> Result<()> {
+        Ok(self.inner.rpc(RemoveLock).await??)
+    }
+
+    pub async fn get_secret_key(&self) -> Result<SecretKey> {
+        match self.secret(NODE_SECRET, true).await? {
+            Some(secret) => Ok(SecretKey::from_bytes(&secret)),
+            None => Err(anyhow!("no node secret")),
+        }
+    }
+
+    // The current key moves to the previous slot
+    pub async fn replace_secret_key(&self, secret: &SecretKey) -> Result<()> {
+        if let Some(old) = self.secret(NODE_SECRET, false).await? {
+            self.set_secret(PREVIOUS_SECRET, old).await?;
+        }
+        self.set_secret(NODE_SECRET, secret.to_bytes()).await
+    }
+
+    // Only looks , a node that never started has none yet
+    pub async fn find_secret_key(&self) -> Result<Option<SecretKey>> {
+        let secret = self.secret(NODE_SECRET, false).await?;
+        Ok(secret.map(|s| SecretKey::from_bytes(&s)))
+    }
+
+    pub async fn previous_secret_key(&self) -> Result<Option<SecretKey>> {
+        let secret = self.secret(PREVIOUS_SECRET, false).await?;
+        Ok(secret.map(|s| SecretKey::from_bytes(&s)))
+    }
+
+    pub async fn rocket_key(&self) -> Result<[u8; 32]> {
+        match self.secret(ROCKET_SECRET, true).await? {
+            Some(secret) => Ok(secret),
+            None => Err(anyhow!("no rocket secret")),
+        }
+    }
+
+    pub async fn add_node(&self, node: PublicKey) -> Result<()> {
+        let node = *node.as_bytes();
+        Ok(self.inner.rpc(AddNode { node }).await??)
+    }
+
+    pub async fn list_nodes(&self) -> Result<Vec<(EndpointId, String)>> {
+        let nodes = self.inner.rpc(ListNodes).await??;
+        let mut res = Vec::new();
+        for (k, v) in nodes {
+            res.push((EndpointId::from_bytes(&k)?, v));
+        }
+        Ok(res)
+    }
+
+    pub async fn get_key(&self, table: KeyTable, name: &str) -> Result<[u8; 32]> {
+        let name = name.to_string();
+        match self.inner.rpc(GetKey { table, name }).await?? {
+            Some(key) => Ok(key),
+            None => Err(anyhow!("key does not exist")),
+        }
+    }
+
+    pub async fn set_key(&self, table: KeyTable, name: &str, value: [u8; 32]) -> Result<()> {
+        let name = name.to_string();
+        Ok(self.inner.rpc(SetKey { table, name, value }).await??)
+    }
+
+    pub async fn list_keys(&self, table: KeyTable) -> Result<Vec<(String, [u8; 32])>> {
+        Ok(self.inner.rpc(ListKeys { table }).await??)
+    }
+
+    pub async fn get_docs_key(&self, name: &str) -> Result<[u8; 32]> {
+        self.get_key(KeyTable::Docs, name).await
+    }
+
+    pub async fn set_docs_key(&self, name: &str, value: [u8; 32]) -> Result<()> {
+        self.set_key(KeyTable::Docs, name, value).await
+    }
+
+    pub async fn get_notes_id(&self) -> Result<[u8; 32]> {
+        self.get_docs_key("notes").await
+    }
+
+    pub async fn get_author_key(&self, name: &str) -> Result<[u8; 32]> {
+        self.get_key(KeyTable::Authors, name).await
+    }
+
+    pub async fn set_author_key(&self, name: &str, value: [u8; 32]) -> Result<()> {
+        self.set_key(KeyTable::Authors, name, value).await
+    }
+
+    pub async fn get_notes_author(&self) -> Result<[u8; 32]> {
+        self.get_author_key("notes").await
+    }
+
+    pub async fn get_setting(&self, name: &str) -> Result<Option<String>> {
+        let name = name.to_string();
+        Ok(self.inner.rpc(GetSetting { name }).await??)
+    }
+
+    // An empty value removes the setting
+    pub async fn set_setting(&self, name: &str, value: &str) -> Result<()> {
+        let name = name.to_string();
+        let value = match value.is_empty() {
+            true => None,
+            false => Some(value.to_string()),
+        };
+        Ok(self.inner.rpc(SetSetting { name, value }).await??)
+    }
+
+    pub async fn list_settings(&self) -> Result<Vec<(String, String)>> {
+        Ok(self.inner.rpc(ListSettings).await??)
+    }
+}
+
+// Binds a sealed secret to its slot
+fn slot_aad(slot: u32) -> Vec<u8> {
+    format!("liminal secret slot {slot}").into_bytes()
 }
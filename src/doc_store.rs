@@ -0,0 +1,358 @@
+// Typed key value store on top of an iroh-docs doc.
+//
+// iroh-docs deletes by prefix , deleting `abc` also takes out `abcd`
+// (https://github.com/n0-computer/iroh-docs/issues/55)
+// so every key is written with a null byte on the end and the null is
+// stripped again on the way out. Nothing outside this file sees it.
+//
+// Values are json with a `_v` version next to the fields. Older readers
+// just ignore it and a missing one means version 0 , each type decides
+// how to bring old versions up to date.
+//
+// Keys starting with a dot are side entries that only point at a blob
+// (put_hash) so the docs downloader fetches it. This store never decodes
+// them as T , list skips them and get_hash hands back the blob hash.
+// What is in the blob is up to the caller , notes.rs reads its own
+// Side format out of it.
+
+use std::marker::PhantomData;
+
+use anyhow::{Context, Result, bail, ensure};
+use bytes::Bytes;
+use iroh_blobs::{BlobsProtocol, Hash};
+use iroh_docs::{
+    AuthorId, Entry,
+    api::Doc,
+    engine::LiveEvent,
+    store::{DownloadPolicy, FilterKind, Query},
+};
+use n0_future::{Stream, StreamExt};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tracing::warn;
+
+const VERSION_FIELD: &str = "_v";
+const AUX_MARK: char = '.';
+
+// Something that can live in a doc. Has to serialize as a json object.
+pub trait Versioned: Serialize + DeserializeOwned {
+    // bump when the layout changes
+    const VERSION: u32;
+    // largest encoded value
+    const MAX_SIZE: usize = 64 * 1024;
+
+    // Bring a stored value of any version up to date
+    fn decode(_version: u32, value: Value) -> Result<Self> {
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+// A stored value and the content hash it came from
+#[derive(Clone, Debug)]
+pub struct Record<T> {
+    pub key: String,
+    pub hash: Hash,
+    // None until the content has been downloaded
+    pub value: Option<T>,
+}
+
+// A key was written , locally or by a peer
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub key: String,
+    pub hash: Hash,
+    pub remote: bool,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct DocStore<T> {
+    doc: Doc,
+    author: AuthorId,
+    blobs: BlobsProtocol,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T: Versioned> DocStore<T> {
+    pub fn new(doc: Doc, author: AuthorId, blobs: BlobsProtocol) -> Self {
+        Self {
+            doc,
+            author,
+            blobs,
+            _value: PhantomData,
+        }
+    }
+
+    pub fn doc(&self) -> &Doc {
+        &self.doc
+    }
+
+    pub fn author(&self) -> AuthorId {
+        self.author
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Record<T>>> {
+        let entry = self
+            .doc
+            .get_one(Query::single_latest_per_key().key_exact(encode_key(key)))
+            .await?;
+        match entry {
+            Some(entry) => Ok(Some(self.record(&entry).await?)),
+            None => Ok(None),
+        }
+    }
+
+    // Content hash of a side entry , they don't decode as T
+    pub async fn get_hash(&self, key: &str) -> Result<Option<Hash>> {
+        let entry = self
+            .doc
+            .get_one(Query::single_latest_per_key().key_exact(encode_key(key)))
+            .await?;
+        Ok(entry.map(|entry| entry.content_hash()))
+    }
+
+    // Hands back the content hash of the new entry
+    pub async fn put(&self, key: &str, value: &T) -> Result<Hash> {
+        check_key(key)?;
+        ensure!(!is_aux_key(key), "{key} is a side entry key");
+        let hash = self
+            .doc
+            .set_bytes(self.author, encode_key(key), encode(value)?)
+            .await?;
+        Ok(hash)
+    }
+
+    // Side entry pointing at a blob that is already in the store
+    pub async fn put_hash(&self, key: &str, hash: Hash, size: u64) -> Result<()> {
+        check_key(key)?;
+        ensure!(is_aux_key(key), "side entry keys start with {AUX_MARK}");
+        self.doc
+            .set_hash(self.author, encode_key(key), hash, size)
+            .await?;
+        Ok(())
+    }
+
+    // Just this key , never its neighbours
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        self.doc.del(self.author, encode_key(key)).await?;
+        Ok(())
+    }
+
+    // Everything under the prefix , side entries included
+    pub async fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        ensure!(!prefix.is_empty(), "refusing to delete the whole doc");
+        self.doc
+            .del(self.author, prefix.as_bytes().to_vec())
+            .await?;
+        Ok(())
+    }
+
+    // Latest value for every key under the prefix , "" for all of them.
+    // A record that won't decode is reported and skipped , not the whole list.
+    pub async fn list(&self, prefix: &str) -> Result<Vec<Record<T>>> {
+        let entries = self
+            .doc
+            .get_many(Query::single_latest_per_key().key_prefix(prefix.as_bytes()))
+            .await?;
+        let mut records = Vec::new();
+        // TODO remove once entries are unpin !
+        tokio::pin!(entries);
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            if entry.key().starts_with(&[AUX_MARK as u8]) {
+                continue;
+            }
+            match self.record(&entry).await {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+        Ok(records)
+    }
+
+    // What this node pulls down from peers , iroh-docs keeps it per doc
+    pub async fn download_policy(&self) -> Result<DownloadPolicy> {
+        self.doc.get_download_policy().await
+    }
+
+    pub async fn set_download_policy(&self, policy: DownloadPolicy) -> Result<()> {
+        self.doc.set_download_policy(policy).await
+    }
+
+    // An older version of a value by its content hash
+    pub async fn load(&self, hash: Hash) -> Result<T> {
+        let bytes = self.blobs.get_bytes(hash).await?;
+        decode(&bytes)
+    }
+
+    pub async fn subscribe(&self) -> Result<impl Stream<Item = Result<Change>> + use<T>> {
+        let events = self.doc.subscribe().await?;
+        Ok(events.filter_map(|event| match event {
+            Ok(LiveEvent::InsertLocal { entry }) => change(&entry, false).map(Ok),
+            Ok(LiveEvent::InsertRemote { entry, .. }) => change(&entry, true).map(Ok),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        }))
+    }
+
+    async fn record(&self, entry: &Entry) -> Result<Record<T>> {
+        let key = decode_key(entry.key())?;
+        let hash = entry.content_hash();
+        let value = match self.blobs.get_bytes(hash).await {
+            Ok(bytes) => Some(decode(&bytes).with_context(|| format!("bad value for {key}"))?),
+            Err(_) => None,
+        };
+        Ok(Record { key, hash, value })
+    }
+}
+
+// Download filters speak raw keys , these match what put and put_hash write
+pub fn exact_filter(key: &str) -> FilterKind {
+    FilterKind::Exact(encode_key(key).into())
+}
+
+pub fn prefix_filter(prefix: &str) -> FilterKind {
+    FilterKind::Prefix(Bytes::copy_from_slice(prefix.as_bytes()))
+}
+
+fn is_aux_key(key: &str) -> bool {
+    key.starts_with(AUX_MARK)
+}
+
+fn check_key(key: &str) -> Result<()> {
+    ensure!(!key.is_empty(), "empty key");
+    ensure!(!key.contains('\0'), "keys can't contain a null byte");
+    Ok(())
+}
+
+fn encode_key(key: &str) -> Vec<u8> {
+    let mut ex_key = key.as_bytes().to_vec();
+    ex_key.push(0);
+    ex_key
+}
+
+// Entries written before the null byte fix come out as they are
+fn decode_key(key: &[u8]) -> Result<String> {
+    let key = key.strip_suffix(&[0]).unwrap_or(key);
+    String::from_utf8(key.to_vec()).context("invalid key")
+}
+
+fn encode<T: Versioned>(value: &T) -> Result<Bytes> {
+    let mut fields = match serde_json::to_value(value)? {
+        Value::Object(fields) => fields,
+        _ => bail!("only json objects can be stored"),
+    };
+    fields.insert(VERSION_FIELD.to_string(), T::VERSION.into());
+    let buf = serde_json::to_vec(&fields)?;
+    ensure!(buf.len() < T::MAX_SIZE, "value is too large, max size is {}", T::MAX_SIZE);
+    Ok(buf.into())
+}
+
+fn decode<T: Versioned>(bytes: &[u8]) -> Result<T> {
+    let mut value: Value = serde_json::from_slice(bytes).context("invalid json")?;
+    let version = match value.as_object_mut().and_then(|o| o.remove(VERSION_FIELD)) {
+        Some(version) => version.as_u64().context("invalid version")? as u32,
+        None => 0,
+    };
+    T::decode(version, value)
+}
+
+fn change(entry: &Entry, remote: bool) -> Option<Change> {
+    Some(Change {
+        key: decode_key(entry.key()).ok()?,
+        hash: entry.content_hash(),
+        remote,
+        deleted: entry.content_len() == 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Thing {
+        name: String,
+        #[serde(default)]
+        count: u32,
+    }
+
+    impl Versioned for Thing {
+        const VERSION: u32 = 2;
+        const MAX_SIZE: usize = 64;
+
+        // version 1 called it label
+        fn decode(version: u32, mut value: Value) -> Result<Self> {
+            let label = match version {
+                0 | 1 => value.as_object_mut().and_then(|o| o.remove("label")),
+                _ => None,
+            };
+            if let Some(label) = label {
+                value["name"] = label;
+            }
+            Ok(serde_json::from_value(value)?)
+        }
+    }
+
+    #[test]
+    fn keys_round_trip_with_the_null() {
+        assert_eq!(encode_key("abc"), b"abc\0".to_vec());
+        assert_eq!(decode_key(b"abc\0").unwrap(), "abc");
+        // written before the null byte fix
+        assert_eq!(decode_key(b"abc").unwrap(), "abc");
+        assert!(decode_key(&[0xff, 0]).is_err());
+    }
+
+    #[test]
+    fn bad_keys() {
+        assert!(check_key("").is_err());
+        assert!(check_key("a\0b").is_err());
+        assert!(check_key("fine/key").is_ok());
+        assert!(is_aux_key(".body/x"));
+        assert!(!is_aux_key("note"));
+    }
+
+    #[test]
+    fn values_carry_the_version() {
+        let thing = Thing {
+            name: "a".to_string(),
+            count: 3,
+        };
+        let bytes = encode(&thing).unwrap();
+        let value: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(value[VERSION_FIELD], 2);
+        assert_eq!(decode::<Thing>(&bytes).unwrap(), thing);
+    }
+
+    #[test]
+    fn old_versions_are_brought_up() {
+        let thing: Thing = decode(br#"{"label":"old","_v":1}"#).unwrap();
+        assert_eq!(thing.name, "old");
+        // no version at all is 0
+        let thing: Thing = decode(br#"{"label":"older"}"#).unwrap();
+        assert_eq!(thing.name, "older");
+    }
+
+    #[test]
+    fn bad_values() {
+        assert!(decode::<Thing>(b"not json").is_err());
+        assert!(decode::<Thing>(br#"{"name":"a","_v":"two"}"#).is_err());
+        let big = Thing {
+            name: "x".repeat(100),
+            count: 0,
+        };
+        assert!(encode(&big).is_err());
+    }
+
+    #[test]
+    fn prefix_filters_skip_the_null() {
+        assert_eq!(
+            prefix_filter(".body/"),
+            FilterKind::Prefix(Bytes::from_static(b".body/"))
+        );
+        assert_eq!(exact_filter("n1"), FilterKind::Exact(Bytes::from_static(b"n1\0")));
+    }
+}
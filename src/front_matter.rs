@@ -0,0 +1,184 @@
+// Front matter for notes.
+// A small yaml-ish block at the very top of a note
+//
+// ---
+// title: Some title
+// tags: [design, iroh]
+// status: draft
+// aliases:
+//   - other name
+// ---
+//
+// Only the keys below are understood, anything else is ignored.
+// Lists can be inline `[a, b]`, comma separated `a, b` or `- item` lines.
+
+use serde::{Deserialize, Serialize};
+
+const FENCE: &str = "---";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    pub status: Option<String>,
+    pub aliases: Vec<String>,
+}
+
+impl FrontMatter {
+    // Split the text into the front matter and the markdown body.
+    // No fence at the top (or no closing fence) means no front matter.
+    pub fn parse(text: &str) -> (FrontMatter, &str) {
+        let mut meta = FrontMatter::default();
+        let Some(rest) = strip_fence(text) else {
+            return (meta, text);
+        };
+        let mut offset = text.len() - rest.len();
+        let mut current: Option<String> = None;
+        for line in rest.split_inclusive('\n') {
+            offset += line.len();
+            let trimmed = line.trim();
+            if trimmed == FENCE {
+                return (meta, &text[offset..]);
+            }
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            // continuation of a block list
+            if let Some(item) = trimmed.strip_prefix("- ") {
+                if let Some(key) = &current {
+                    meta.push(key, item);
+                }
+                continue;
+            }
+            match trimmed.split_once(':') {
+                Some((key, value)) => {
+                    let key = key.trim().to_lowercase();
+                    let value = value.trim();
+                    if value.is_empty() {
+                        current = Some(key);
+                    } else {
+                        current = None;
+                        meta.set(&key, value);
+                    }
+                }
+                None => current = None,
+            }
+        }
+        // never closed , treat it all as text
+        (FrontMatter::default(), text)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == FrontMatter::default()
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "title" => self.title = Some(unquote(value)),
+            "status" => self.status = Some(unquote(value)),
+            "tags" | "aliases" => {
+                let value = value
+                    .strip_prefix('[')
+                    .and_then(|v| v.strip_suffix(']'))
+                    .unwrap_or(value);
+                for item in value.split(',') {
+                    self.push(key, item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, key: &str, item: &str) {
+        let (list, item) = match key {
+            "tags" => (&mut self.tags, unquote(item).trim_start_matches('#').to_string()),
+            "aliases" => (&mut self.aliases, unquote(item)),
+            _ => return,
+        };
+        if item.is_empty() {
+            return;
+        }
+        if !list.contains(&item) {
+            list.push(item);
+        }
+    }
+}
+
+fn strip_fence(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix(FENCE)?;
+    let rest = rest.trim_start_matches([' ', '\t']);
+    rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n'))
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .trim()
+        .trim_matches(|c| c == '"' || c == '\'')
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_and_block_lists() {
+        let text = "---\ntags: [design, #iroh, design]\naliases:\n  - other name\n  - 'quoted'\n---\nbody\n";
+        let (meta, body) = FrontMatter::parse(text);
+        assert_eq!(meta.tags, vec!["design", "iroh"]);
+        assert_eq!(meta.aliases, vec!["other name", "quoted"]);
+        assert_eq!(body, "body\n");
+    }
+
+    #[test]
+    fn comma_separated_list() {
+        let (meta, _) = FrontMatter::parse("---\ntags: a, b ,c\n---\n");
+        assert_eq!(meta.tags, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn quoted_values() {
+        let text = "---\ntitle: \"Colons: fine\"\nstatus: 'draft'\n---\n";
+        let (meta, body) = FrontMatter::parse(text);
+        assert_eq!(meta.title.as_deref(), Some("Colons: fine"));
+        assert_eq!(meta.status.as_deref(), Some("draft"));
+        assert_eq!(body, "");
+    }
+
+    #[test]
+    fn missing_closing_fence_is_all_text() {
+        let text = "---\ntitle: never closed\nbody\n";
+        let (meta, body) = FrontMatter::parse(text);
+        assert!(meta.is_empty());
+        assert_eq!(body, text);
+    }
+
+    #[test]
+    fn no_front_matter() {
+        let text = "# Heading\n---\n";
+        let (meta, body) = FrontMatter::parse(text);
+        assert!(meta.is_empty());
+        assert_eq!(body, text);
+    }
+
+    #[test]
+    fn crlf_input() {
+        let text = "---\r\ntitle: Windows\r\ntags:\r\n  - one\r\n---\r\nbody\r\n";
+        let (meta, body) = FrontMatter::parse(text);
+        assert_eq!(meta.title.as_deref(), Some("Windows"));
+        assert_eq!(meta.tags, vec!["one"]);
+        assert_eq!(body, "body\r\n");
+    }
+
+    #[test]
+    fn unknown_keys_ignored() {
+        let (meta, _) = FrontMatter::parse("---\ncolour: red\ntitle: t\n---\n");
+        assert_eq!(meta.title.as_deref(), Some("t"));
+        assert!(!meta.has_tag("red"));
+    }
+}
@@ -1,14 +1,21 @@
 // A redb backed actor to handle Endpoint ids
 
-use std::collections::BTreeMap;
-
-use iroh::EndpointId;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail};
+use iroh::{EndpointAddr, EndpointId};
+use iroh_blobs::Hash;
 use irpc::{Client, WithChannels, channel::oneshot, rpc_requests};
 use postcard::{from_bytes, to_stdvec};
-use redb::{Database, ReadableDatabase, ReadableTable, Table, TableDefinition, TypeName, Value, WriteTransaction};
+use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition, TypeName, Value};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Sender;
 
+use crate::schema::{self, Migration};
+
 // Stored endpoint data
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Status {
@@ -20,33 +27,144 @@ pub enum Status {
     DestroyOnSight,
 }
 
+impl FromStr for Status {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let status = match s.to_lowercase().as_str() {
+            "seen" => Status::Seen,
+            "known" => Status::Known,
+            "apparent" => Status::Apparent,
+            "fren" => Status::Fren,
+            "enemy" => Status::Enemy,
+            "destroy-on-sight" | "destroyonsight" => Status::DestroyOnSight,
+            other => bail!("unknown status {other}"),
+        };
+        Ok(status)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Fren {
+    name: String,
+    id: EndpointId,
+    status: Status,
+    // nanos
+    created: i64,
+    // unix seconds , 0 for never
+    last_seen: i64,
+    // last full address , direct and relay , for dialing without discovery
+    addr: Option<EndpointAddr>,
+    // incoming connections
+    connections: u64,
+    // gossip , blobs , docs , ticket ...
+    protocols: BTreeSet<String>,
+}
+
+// Fren before sightings , for migrations only
+#[derive(Deserialize)]
+struct FrenV1 {
     name: String,
     id: EndpointId,
     status: Status,
     created: i64,
 }
 
+// Someone's word on someone else , as it came off gossip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vouch {
+    pub voucher: EndpointId,
+    pub subject: EndpointId,
+    // false takes it back
+    pub trusted: bool,
+    pub timestamp: i64,
+    // the signed announcement , kept to check and pass on
+    pub signed: Vec<u8>,
+}
+
+// What a peer says about itself , signed and sent over gossip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: EndpointId,
+    pub nickname: String,
+    // a blob on the peer
+    pub avatar: Option<Hash>,
+    pub contact: Option<String>,
+    // gossip , blobs , docs ...
+    pub capabilities: Vec<String>,
+    pub timestamp: i64,
+    // the signed announcement
+    pub signed: Vec<u8>,
+}
+
 impl Fren {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn last_seen(&self) -> i64 {
+        self.last_seen
+    }
+
+    pub fn id(&self) -> EndpointId {
+        self.id
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    pub fn addr(&self) -> Option<&EndpointAddr> {
+        self.addr.as_ref()
+    }
+
+    // for trust tests , records otherwise only come out of the store
+    #[cfg(test)]
+    pub fn with_status(id: EndpointId, status: Status) -> Self {
+        Self {
+            status,
+            ..Self::new(id)
+        }
+    }
+
     fn new(id: EndpointId) -> Self {
         Self {
             name: "_".to_string(),
             id: id,
             status: Status::Seen,
-            created: chrono::Utc::now().timestamp_nanos_opt().expect("time does not exist")
+            created: chrono::Utc::now().timestamp_nanos_opt().expect("time does not exist"),
+            last_seen: 0,
+            addr: None,
+            connections: 0,
+            protocols: BTreeSet::new(),
         }
     }
 }
-// KV impl
-impl Value for Fren {
+
+impl From<FrenV1> for Fren {
+    fn from(old: FrenV1) -> Self {
+        Self {
+            name: old.name,
+            status: old.status,
+            created: old.created,
+            ..Fren::new(old.id)
+        }
+    }
+}
+// Stored as postcard bytes and decoded outside of redb ,
+// from_bytes can't fail so a bad row would panic in there.
+// The type name stays Fren so tables made before this still open.
+#[derive(Debug)]
+struct FrenBytes;
+
+impl Value for FrenBytes {
     type SelfType<'a>
-        = Fren
+        = &'a [u8]
     where
         Self: 'a;
 
     type AsBytes<'a>
-        = Vec<u8>
+        = &'a [u8]
     where
         Self: 'a;
 
@@ -58,14 +176,14 @@ impl Value for Fren {
     where
         Self: 'a,
     {
-        postcard::from_bytes(data).unwrap()
+        data
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
     where
         Self: 'b,
     {
-        to_stdvec(value).unwrap()
+        value
     }
 
     fn type_name() -> redb::TypeName {
@@ -73,8 +191,130 @@ impl Value for Fren {
     }
 }
 
+fn decode(key: &[u8; 32], data: &[u8]) -> Result<Fren> {
+    from_bytes(data).with_context(|| {
+        let id = EndpointId::from_bytes(key).map(|id| id.to_string());
+        format!("bad fren record {}", id.unwrap_or_default())
+    })
+}
+
 // Database
-const NODE_TABLE: TableDefinition<&[u8; 32], Fren> = TableDefinition::new("nodes");
+const NODE_TABLE: TableDefinition<&[u8; 32], FrenBytes> = TableDefinition::new("nodes");
+// rows that would not decode , kept for a look rather than thrown away
+const BROKEN_TABLE: TableDefinition<&[u8; 32], &[u8]> = TableDefinition::new("nodes_broken");
+// voucher id then subject id , latest vouch only
+const VOUCH_TABLE: TableDefinition<&[u8; 64], &[u8]> = TableDefinition::new("vouches");
+// latest profile per endpoint
+const PROFILE_TABLE: TableDefinition<&[u8; 32], &[u8]> = TableDefinition::new("profiles");
+// retired id to the timestamp of the rotation that moved it , each id moves once
+const ROTATION_TABLE: TableDefinition<&[u8; 32], i64> = TableDefinition::new("rotations");
+
+// Every stranger that connects gets a Seen record , past this many the
+// ones seen longest ago are dropped. Anything promoted is kept.
+const MAX_SEEN: usize = 1024;
+
+// Postcard is positional , a change to Fren or Status needs a migration
+// that decodes with a copy of the old layout (FrenV1 ..) and writes the new one.
+// Older migrations keep using the layout of their time.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to: 1,
+        about: "create tables",
+        run: |tx| {
+            tx.open_table(NODE_TABLE)?;
+            Ok(())
+        },
+    },
+    Migration {
+        to: 2,
+        about: "move undecodable nodes aside",
+        run: |tx| {
+            let mut nodes = tx.open_table(NODE_TABLE)?;
+            let mut broken = tx.open_table(BROKEN_TABLE)?;
+            let mut bad = Vec::new();
+            for item in nodes.iter()? {
+                let (k, v) = item?;
+                if from_bytes::<FrenV1>(v.value()).is_err() {
+                    bad.push((*k.value(), v.value().to_vec()));
+                }
+            }
+            for (key, data) in bad {
+                println!("moving bad node record aside {}", data_encoding::HEXLOWER.encode(&key));
+                broken.insert(&key, data.as_slice())?;
+                nodes.remove(&key)?;
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        to: 3,
+        about: "add sightings to nodes",
+        run: |tx| {
+            let mut nodes = tx.open_table(NODE_TABLE)?;
+            let mut upgraded = Vec::new();
+            for item in nodes.iter()? {
+                let (k, v) = item?;
+                let old: FrenV1 = from_bytes(v.value()).context("bad node record")?;
+                upgraded.push((*k.value(), to_stdvec(&Fren::from(old))?));
+            }
+            for (key, data) in upgraded {
+                nodes.insert(&key, data.as_slice())?;
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        to: 4,
+        about: "add vouches",
+        run: |tx| {
+            tx.open_table(VOUCH_TABLE)?;
+            Ok(())
+        },
+    },
+    Migration {
+        to: 5,
+        about: "add profiles",
+        run: |tx| {
+            tx.open_table(PROFILE_TABLE)?;
+            Ok(())
+        },
+    },
+    Migration {
+        to: 6,
+        about: "add rotations",
+        run: |tx| {
+            tx.open_table(ROTATION_TABLE)?;
+            Ok(())
+        },
+    },
+];
+
+fn vouch_key(voucher: &EndpointId, subject: &EndpointId) -> [u8; 64] {
+    let mut key = [0u8; 64];
+    key[..32].copy_from_slice(voucher.as_bytes());
+    key[32..].copy_from_slice(subject.as_bytes());
+    key
+}
+
+// Errors come back over the channel as text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdError(String);
+
+impl fmt::Display for IdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for IdError {}
+
+impl From<anyhow::Error> for IdError {
+    fn from(e: anyhow::Error) -> Self {
+        Self(format!("{e:#}"))
+    }
+}
+
+type Reply<T> = Result<T, IdError>;
 
 // Irpc
 
@@ -92,6 +332,42 @@ struct Set {
 #[derive(Debug, Serialize, Deserialize)]
 struct List;
 
+// Stored if it is newer than what we have
+#[derive(Debug, Serialize, Deserialize)]
+struct AddVouch {
+    vouch: Vouch,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ListVouches;
+
+// Stored if newer and the id has a record , the nickname becomes the
+// name once the id is Known or better
+#[derive(Debug, Serialize, Deserialize)]
+struct AddProfile {
+    profile: Profile,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ListProfiles;
+
+// Trust moves from old to new , the signatures are checked by the caller
+#[derive(Debug, Serialize, Deserialize)]
+struct Rotate {
+    old: EndpointId,
+    new: EndpointId,
+    timestamp: i64,
+}
+
+// Saw a peer , made if it is new
+#[derive(Debug, Serialize, Deserialize)]
+struct Sighting {
+    key: EndpointId,
+    addr: Option<EndpointAddr>,
+    protocol: String,
+    connection: bool,
+}
+
 
 impl From<(EndpointId, Fren)> for Set {
     fn from((key, value): (EndpointId, Fren)) -> Self {
@@ -102,12 +378,24 @@ impl From<(EndpointId, Fren)> for Set {
 #[rpc_requests(message = IdentityMessage, no_rpc, no_spans)]
 #[derive(Serialize, Deserialize, Debug)]
 enum StorageProtocol {
-    #[rpc(tx=oneshot::Sender<Option<Fren>>)]
+    #[rpc(tx=oneshot::Sender<Reply<Option<Fren>>>)]
     Get(Get),
-    #[rpc(tx=oneshot::Sender<()>)]
+    #[rpc(tx=oneshot::Sender<Reply<()>>)]
     Set(Set),
-    #[rpc(tx=oneshot::Sender<Vec<Fren>>)]
+    #[rpc(tx=oneshot::Sender<Reply<Vec<Fren>>>)]
     List(List),
+    #[rpc(tx=oneshot::Sender<Reply<()>>)]
+    Sighting(Sighting),
+    #[rpc(tx=oneshot::Sender<Reply<bool>>)]
+    AddVouch(AddVouch),
+    #[rpc(tx=oneshot::Sender<Reply<Vec<Vouch>>>)]
+    ListVouches(ListVouches),
+    #[rpc(tx=oneshot::Sender<Reply<bool>>)]
+    AddProfile(AddProfile),
+    #[rpc(tx=oneshot::Sender<Reply<Vec<Profile>>>)]
+    ListProfiles(ListProfiles),
+    #[rpc(tx=oneshot::Sender<Reply<bool>>)]
+    Rotate(Rotate),
 }
 
 struct Actor {
@@ -126,42 +414,267 @@ impl Actor {
         match msg {
             IdentityMessage::Get(get) => {
                 let WithChannels { tx, inner, .. } = get;
-                let read_txn = self.db.begin_read().unwrap();
-                let table = read_txn.open_table(NODE_TABLE).unwrap();
-                let key = inner.key.as_array().expect("bad key");
-                let value = match table.get(key).unwrap() {
-                    Some(value) => Some(value.value()),
-                    None => None,
-                };
-                tx.send(value).await.ok();
+                tx.send(self.get(&inner.key).map_err(Into::into)).await.ok();
             }
 
             IdentityMessage::Set(set) => {
                 let WithChannels { tx, inner, .. } = set;
-                let write_txn = self.db.begin_write().unwrap();
-                {
-                    let mut table = write_txn.open_table(NODE_TABLE).unwrap();
-                    let key = inner.key.as_array().expect("bad key");
-                    table.insert(key, inner.value).unwrap();
-                }
-                write_txn.commit().unwrap();
-
-                tx.send(()).await.ok();
+                tx.send(self.set(&inner.key, &inner.value).map_err(Into::into))
+                    .await
+                    .ok();
             }
 
             IdentityMessage::List(list) => {
                 let WithChannels{ tx , .. } = list;
-                let read_txn = self.db.begin_read().unwrap();
-                let table = read_txn.open_table(NODE_TABLE).unwrap();
-                let mut res = Vec::new();
-                for item in  table.iter().unwrap(){
-                    let (_,item) = item.unwrap();
-                    res.push(item.value());
+                tx.send(self.list().map_err(Into::into)).await.ok();
+            }
+
+            IdentityMessage::Sighting(sighting) => {
+                let WithChannels { tx, inner, .. } = sighting;
+                tx.send(self.sighting(inner).map_err(Into::into)).await.ok();
+            }
+
+            IdentityMessage::AddVouch(add) => {
+                let WithChannels { tx, inner, .. } = add;
+                tx.send(self.add_vouch(&inner.vouch).map_err(Into::into))
+                    .await
+                    .ok();
+            }
+
+            IdentityMessage::ListVouches(list) => {
+                let WithChannels { tx, .. } = list;
+                tx.send(self.list_vouches().map_err(Into::into)).await.ok();
+            }
+
+            IdentityMessage::AddProfile(add) => {
+                let WithChannels { tx, inner, .. } = add;
+                tx.send(self.add_profile(&inner.profile).map_err(Into::into))
+                    .await
+                    .ok();
+            }
+
+            IdentityMessage::ListProfiles(list) => {
+                let WithChannels { tx, .. } = list;
+                tx.send(self.list_profiles().map_err(Into::into)).await.ok();
+            }
+
+            IdentityMessage::Rotate(rotate) => {
+                let WithChannels { tx, inner, .. } = rotate;
+                tx.send(self.rotate(&inner).map_err(Into::into)).await.ok();
+            }
+        }
+    }
+
+    // Check and move in one transaction so a replay can't get in between.
+    // Only trust moves , an enemy that rotates stays an enemy on the old id
+    // and a rotation can't make anyone else one. The old id drops back to
+    // Seen and can't be moved again.
+    fn rotate(&self, rotate: &Rotate) -> Result<bool> {
+        let old = rotate.old.as_bytes();
+        let new = rotate.new.as_bytes();
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut rotations = write_txn.open_table(ROTATION_TABLE)?;
+            if rotations.get(old)?.is_some() {
+                return Ok(false);
+            }
+            let mut nodes = write_txn.open_table(NODE_TABLE)?;
+            let fren = match nodes.get(old)? {
+                Some(value) => decode(old, value.value())?,
+                None => return Ok(false),
+            };
+            if !matches!(fren.status, Status::Known | Status::Apparent | Status::Fren) {
+                return Ok(false);
+            }
+            // don't overwrite anything already decided about the new id
+            let existing = match nodes.get(new)? {
+                Some(value) => Some(decode(new, value.value())?),
+                None => None,
+            };
+            if existing.is_some_and(|fren| !matches!(fren.status, Status::Seen)) {
+                return Ok(false);
+            }
+            let moved = Fren {
+                id: rotate.new,
+                ..fren.clone()
+            };
+            let retired = Fren {
+                status: Status::Seen,
+                ..fren
+            };
+            nodes.insert(new, to_stdvec(&moved)?.as_slice())?;
+            nodes.insert(old, to_stdvec(&retired)?.as_slice())?;
+            rotations.insert(old, rotate.timestamp)?;
+        }
+        write_txn.commit()?;
+        Ok(true)
+    }
+
+    // Strangers don't get a record out of a profile , and a name only
+    // counts for peers something already vouched for
+    fn add_profile(&self, profile: &Profile) -> Result<bool> {
+        let key = profile.id.as_bytes();
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut nodes = write_txn.open_table(NODE_TABLE)?;
+            let fren = match nodes.get(key)? {
+                Some(value) => decode(key, value.value())?,
+                None => return Ok(false),
+            };
+            let mut table = write_txn.open_table(PROFILE_TABLE)?;
+            if let Some(existing) = table.get(key)? {
+                if let Ok(existing) = from_bytes::<Profile>(existing.value()) {
+                    if existing.timestamp >= profile.timestamp {
+                        return Ok(false);
+                    }
                 }
-                tx.send(res).await.ok();
             }
+            table.insert(key, to_stdvec(profile)?.as_slice())?;
+
+            if matches!(fren.status, Status::Known | Status::Apparent | Status::Fren) {
+                let fren = Fren {
+                    name: profile.nickname.clone(),
+                    ..fren
+                };
+                nodes.insert(key, to_stdvec(&fren)?.as_slice())?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(true)
+    }
+
+    fn list_profiles(&self) -> Result<Vec<Profile>> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(PROFILE_TABLE)?;
+        let mut res = Vec::new();
+        for item in table.iter()? {
+            let (_, value) = item?;
+            match from_bytes::<Profile>(value.value()) {
+                Ok(profile) => res.push(profile),
+                Err(e) => warn!("bad profile record {:?}", e),
+            }
+        }
+        Ok(res)
+    }
+
+    fn add_vouch(&self, vouch: &Vouch) -> Result<bool> {
+        let key = vouch_key(&vouch.voucher, &vouch.subject);
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(VOUCH_TABLE)?;
+            if let Some(existing) = table.get(&key)? {
+                // a bad old one just gets replaced
+                if let Ok(existing) = from_bytes::<Vouch>(existing.value()) {
+                    if existing.timestamp >= vouch.timestamp {
+                        return Ok(false);
+                    }
+                }
+            }
+            table.insert(&key, to_stdvec(vouch)?.as_slice())?;
+        }
+        write_txn.commit()?;
+        Ok(true)
+    }
+
+    fn list_vouches(&self) -> Result<Vec<Vouch>> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(VOUCH_TABLE)?;
+        let mut res = Vec::new();
+        for item in table.iter()? {
+            let (_, value) = item?;
+            match from_bytes::<Vouch>(value.value()) {
+                Ok(vouch) => res.push(vouch),
+                Err(e) => warn!("bad vouch record {:?}", e),
+            }
+        }
+        Ok(res)
+    }
+
+    // Read and write in one go so sightings don't race each other
+    fn sighting(&self, sighting: Sighting) -> Result<()> {
+        let Sighting {
+            key,
+            addr,
+            protocol,
+            connection,
+        } = sighting;
+        let existing = self.get(&key)?;
+        let fresh = existing.is_none();
+        let mut fren = existing.unwrap_or_else(|| Fren::new(key));
+        fren.last_seen = chrono::Utc::now().timestamp();
+        if let Some(addr) = addr {
+            fren.addr = Some(addr);
+        }
+        if connection {
+            fren.connections += 1;
+        }
+        fren.protocols.insert(protocol);
+        self.set(&key, &fren)?;
+        if fresh {
+            self.prune_seen(MAX_SEEN)?;
+        }
+        Ok(())
+    }
+
+    // Oldest Seen records beyond keep go , their profiles with them
+    fn prune_seen(&self, keep: usize) -> Result<()> {
+        let mut seen: Vec<Fren> = self
+            .list()?
+            .into_iter()
+            .filter(|fren| matches!(fren.status, Status::Seen))
+            .collect();
+        if seen.len() <= keep {
+            return Ok(());
+        }
+        seen.sort_by_key(|fren| fren.last_seen);
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(NODE_TABLE)?;
+            let mut profiles = write_txn.open_table(PROFILE_TABLE)?;
+            for fren in &seen[..seen.len() - keep] {
+                table.remove(fren.id.as_bytes())?;
+                profiles.remove(fren.id.as_bytes())?;
+            }
+        }
+        write_txn.commit()?;
+        debug!("dropped {} old sightings", seen.len() - keep);
+        Ok(())
+    }
+
+    fn get(&self, key: &EndpointId) -> Result<Option<Fren>> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(NODE_TABLE)?;
+        match table.get(key.as_bytes())? {
+            Some(value) => Ok(Some(decode(key.as_bytes(), value.value())?)),
+            None => Ok(None),
         }
     }
+
+    fn set(&self, key: &EndpointId, value: &Fren) -> Result<()> {
+        let data = to_stdvec(value)?;
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(NODE_TABLE)?;
+            table.insert(key.as_bytes(), data.as_slice())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    // A bad row is reported and skipped , not the whole list
+    fn list(&self) -> Result<Vec<Fren>> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(NODE_TABLE)?;
+        let mut res = Vec::new();
+        for item in table.iter()? {
+            let (key, value) = item?;
+            match decode(key.value(), value.value()) {
+                Ok(fren) => res.push(fren),
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+        Ok(res)
+    }
 }
 
 pub struct IdentityApi {
@@ -169,18 +682,17 @@ pub struct IdentityApi {
 }
 
 impl IdentityApi {
-    pub fn spawn(file_name: &str) -> IdentityApi {
+    pub fn spawn(file_name: &Path) -> Result<IdentityApi> {
         let (tx, rx) = tokio::sync::mpsc::channel(1);
         //Create the database
-        let db = Database::create(file_name).unwrap();
-        let write_txn = db.begin_write().unwrap();
-        let _ = write_txn.open_table(NODE_TABLE).unwrap();
-        write_txn.commit().unwrap();
+        let db = Database::create(file_name)
+            .with_context(|| format!("bad database create {}", file_name.display()))?;
+        schema::migrate(&db, "id.rdb", MIGRATIONS)?;
 
         let actor = Actor { recv: rx, db: db };
         n0_future::task::spawn(actor.run());
 
-        IdentityApi { tx: tx.clone() }
+        Ok(IdentityApi { tx: tx.clone() })
     }
 
     pub fn client(&self) -> IdClient {
@@ -196,34 +708,264 @@ pub struct IdClient {
 }
 
 impl IdClient {
-    pub async fn get(&self, key: EndpointId) -> irpc::Result<Option<Fren>> {
+    pub async fn get(&self, key: EndpointId) -> Result<Option<Fren>> {
         info!("get {} ", key);
-        self.inner.rpc(Get { key }).await
+        Ok(self.inner.rpc(Get { key }).await??)
     }
 
-    pub async fn new_fren(&self, key: EndpointId) {
-        match self.inner.rpc(Get { key }).await.unwrap() {
+    pub async fn new_fren(&self, key: EndpointId) -> Result<()> {
+        match self.get(key).await? {
             Some(fren) => { 
                 warn!("Fren {:#?}",fren);
-                return
             },
             None => {
                 let value = Fren::new(key);
-                self.inner.rpc(Set { key, value }).await.unwrap();
+                self.set(key, value).await?;
             }
         }
+        Ok(())
     }
 
-    pub async fn set(&self, key: EndpointId, value: Fren) -> irpc::Result<()> {
-        self.inner.rpc(Set { key, value }).await
+    pub async fn set(&self, key: EndpointId, value: Fren) -> Result<()> {
+        Ok(self.inner.rpc(Set { key, value }).await??)
     }
 
     pub async fn is_fren(&self, key: EndpointId) -> bool {
-        let _val = self.inner.rpc(Get { key }).await;
-        false
+        match self.get(key).await {
+            Ok(Some(fren)) => matches!(fren.status, Status::Fren),
+            _ => false,
+        }
+    }
+
+    // Heard of it , from gossip or a ticket
+    pub async fn seen(&self, key: EndpointId, addr: Option<EndpointAddr>, protocol: &str) -> Result<()> {
+        let protocol = protocol.to_string();
+        let sighting = Sighting {
+            key,
+            addr,
+            protocol,
+            connection: false,
+        };
+        Ok(self.inner.rpc(sighting).await??)
+    }
+
+    // It connected to us , addr is the path the endpoint has for it
+    pub async fn connected(
+        &self,
+        key: EndpointId,
+        addr: Option<EndpointAddr>,
+        protocol: &str,
+    ) -> Result<()> {
+        let protocol = protocol.to_string();
+        let sighting = Sighting {
+            key,
+            addr,
+            protocol,
+            connection: true,
+        };
+        Ok(self.inner.rpc(sighting).await??)
+    }
+
+    // True when it was newer than the one we had
+    pub async fn add_vouch(&self, vouch: Vouch) -> Result<bool> {
+        Ok(self.inner.rpc(AddVouch { vouch }).await??)
+    }
+
+    pub async fn list_vouches(&self) -> Result<Vec<Vouch>> {
+        Ok(self.inner.rpc(ListVouches).await??)
+    }
+
+    // True when it was newer than the one we had
+    pub async fn add_profile(&self, profile: Profile) -> Result<bool> {
+        Ok(self.inner.rpc(AddProfile { profile }).await??)
+    }
+
+    pub async fn list_profiles(&self) -> Result<Vec<Profile>> {
+        Ok(self.inner.rpc(ListProfiles).await??)
+    }
+
+    // Stored addresses of everyone not marked hostile
+    pub async fn addrs(&self) -> Result<Vec<EndpointAddr>> {
+        let addrs = self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|fren| !matches!(fren.status, Status::Enemy | Status::DestroyOnSight))
+            .filter_map(|fren| fren.addr)
+            .collect();
+        Ok(addrs)
+    }
+
+    // Carry trust over from a rotated key to its new id ,
+    // the old id drops back to Seen. False when there was nothing to move.
+    pub async fn rotate(&self, old: EndpointId, new: EndpointId, timestamp: i64) -> Result<bool> {
+        let rotate = Rotate {
+            old,
+            new,
+            timestamp,
+        };
+        Ok(self.inner.rpc(rotate).await??)
+    }
+
+    // Makes a record for ids it hasn't seen
+    pub async fn set_status(&self, key: EndpointId, status: Status) -> Result<Fren> {
+        let fren = match self.get(key).await? {
+            Some(fren) => Fren { status, ..fren },
+            None => Fren {
+                status,
+                ..Fren::new(key)
+            },
+        };
+        self.set(key, fren.clone()).await?;
+        Ok(fren)
+    }
+
+    pub async fn list(&self) -> Result<Vec<Fren>> { 
+        Ok(self.inner.rpc(List {}).await??)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::SecretKey;
+    use redb::backends::InMemoryBackend;
+
+    use super::*;
+
+    fn actor() -> Actor {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        schema::migrate(&db, "test", MIGRATIONS).unwrap();
+        let (_tx, recv) = tokio::sync::mpsc::channel(1);
+        Actor { recv, db }
+    }
+
+    fn id(n: u8) -> EndpointId {
+        SecretKey::from_bytes(&[n; 32]).public()
+    }
+
+    fn fren(n: u8, status: Status, last_seen: i64) -> Fren {
+        Fren {
+            status,
+            last_seen,
+            ..Fren::new(id(n))
+        }
+    }
+
+    #[test]
+    fn sightings_fill_in_the_record() {
+        let actor = actor();
+        let sighting = Sighting {
+            key: id(1),
+            addr: Some(EndpointAddr::new(id(1))),
+            protocol: "blobs".to_string(),
+            connection: true,
+        };
+        actor.sighting(sighting).unwrap();
+        let sighting = Sighting {
+            key: id(1),
+            addr: None,
+            protocol: "gossip".to_string(),
+            connection: false,
+        };
+        actor.sighting(sighting).unwrap();
+        let fren = actor.get(&id(1)).unwrap().unwrap();
+        assert!(matches!(fren.status, Status::Seen));
+        assert!(fren.addr.is_some());
+        assert_eq!(fren.connections, 1);
+        assert_eq!(fren.protocols.len(), 2);
+    }
+
+    fn profile(n: u8, nickname: &str, timestamp: i64) -> Profile {
+        Profile {
+            id: id(n),
+            nickname: nickname.to_string(),
+            avatar: None,
+            contact: None,
+            capabilities: vec![],
+            timestamp,
+            signed: vec![],
+        }
+    }
+
+    #[test]
+    fn profiles_name_known_peers_only() {
+        let actor = actor();
+        // never heard of , nothing gets made
+        assert!(!actor.add_profile(&profile(1, "stranger", 1)).unwrap());
+        assert!(actor.get(&id(1)).unwrap().is_none());
+
+        actor.set(&id(2), &fren(2, Status::Seen, 0)).unwrap();
+        assert!(actor.add_profile(&profile(2, "seen", 1)).unwrap());
+        assert_eq!(actor.get(&id(2)).unwrap().unwrap().name(), "_");
+
+        actor.set(&id(3), &fren(3, Status::Known, 0)).unwrap();
+        assert!(actor.add_profile(&profile(3, "known", 1)).unwrap());
+        assert_eq!(actor.get(&id(3)).unwrap().unwrap().name(), "known");
+        // older ones don't replace it
+        assert!(!actor.add_profile(&profile(3, "older", 0)).unwrap());
+        assert_eq!(actor.list_profiles().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn oldest_sightings_are_dropped() {
+        let actor = actor();
+        actor.set(&id(1), &fren(1, Status::Seen, 10)).unwrap();
+        actor.set(&id(2), &fren(2, Status::Seen, 30)).unwrap();
+        actor.set(&id(3), &fren(3, Status::Seen, 20)).unwrap();
+        actor.set(&id(4), &fren(4, Status::Fren, 0)).unwrap();
+        actor.prune_seen(2).unwrap();
+        assert!(actor.get(&id(1)).unwrap().is_none());
+        assert!(actor.get(&id(2)).unwrap().is_some());
+        assert!(actor.get(&id(3)).unwrap().is_some());
+        // promoted ones stay however old
+        assert!(actor.get(&id(4)).unwrap().is_some());
+    }
+
+    fn status(actor: &Actor, n: u8) -> Status {
+        actor.get(&id(n)).unwrap().unwrap().status
+    }
+
+    fn rotation(old: u8, new: u8, timestamp: i64) -> Rotate {
+        Rotate {
+            old: id(old),
+            new: id(new),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn rotations_move_trust_once() {
+        let actor = actor();
+        actor.set(&id(1), &fren(1, Status::Fren, 0)).unwrap();
+        assert!(actor.rotate(&rotation(1, 2, 10)).unwrap());
+        assert!(matches!(status(&actor, 2), Status::Fren));
+        assert!(matches!(status(&actor, 1), Status::Seen));
+        // a replay , even after the old id is trusted again
+        actor.set(&id(1), &fren(1, Status::Fren, 0)).unwrap();
+        assert!(!actor.rotate(&rotation(1, 3, 20)).unwrap());
+        assert!(actor.get(&id(3)).unwrap().is_none());
+    }
+
+    #[test]
+    fn rotations_never_move_hostile_ids() {
+        let actor = actor();
+        actor.set(&id(1), &fren(1, Status::Enemy, 0)).unwrap();
+        actor.set(&id(2), &fren(2, Status::Seen, 0)).unwrap();
+        assert!(!actor.rotate(&rotation(1, 2, 10)).unwrap());
+        assert!(matches!(status(&actor, 1), Status::Enemy));
+        assert!(matches!(status(&actor, 2), Status::Seen));
     }
 
-    pub async fn list(&self) -> irpc::Result<Vec<Fren>> { 
-        self.inner.rpc(List {}).await
+    #[test]
+    fn rotations_keep_what_is_decided_about_the_new_id() {
+        let actor = actor();
+        actor.set(&id(1), &fren(1, Status::Fren, 0)).unwrap();
+        let hostile = fren(2, Status::DestroyOnSight, 0);
+        actor.set(&id(2), &hostile).unwrap();
+        assert!(!actor.rotate(&rotation(1, 2, 10)).unwrap());
+        assert!(matches!(status(&actor, 1), Status::Fren));
+        assert!(matches!(status(&actor, 2), Status::DestroyOnSight));
     }
 }
@@ -0,0 +1,128 @@
+// Backing up , moving and rotating the node identity.
+// These run offline from the cli , the node must not be running since
+// they open config.db and the docs store directly.
+//
+// A bundle is a json file holding the node secret , the doc pointers and
+// the named docs authors with their secrets (out of the docs store) ,
+// locked with a passphrase.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use iroh::SecretKey;
+use iroh_docs::{Author, AuthorId, store::fs::Store};
+use serde::{Deserialize, Serialize};
+
+use crate::announce;
+use crate::config::{ConfigClient, KeyTable};
+use crate::passphrase::{self, Locked};
+
+const BUNDLE_VERSION: u32 = 1;
+const BUNDLE_AAD: &[u8] = b"liminal identity bundle";
+
+// What is on disk
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleFile {
+    version: u32,
+    // endpoint id , so you can tell bundles apart without the passphrase
+    node: String,
+    locked: Locked,
+}
+
+// What is inside
+#[derive(Serialize, Deserialize)]
+struct Bundle {
+    node: [u8; 32],
+    docs: Vec<(String, [u8; 32])>,
+    // name and author secret
+    authors: Vec<(String, [u8; 32])>,
+}
+
+// Same file Docs::persistent uses
+pub fn docs_store(data_dir: &Path) -> Result<Store> {
+    let path: PathBuf = data_dir.join("docs.redb");
+    Store::persistent(&path).with_context(|| format!("can't open {}", path.display()))
+}
+
+pub async fn export(conf: &ConfigClient, data_dir: &Path, file: &Path) -> Result<()> {
+    if file.exists() {
+        bail!("{} already exists", file.display());
+    }
+    let secret = conf.get_secret_key().await?;
+    let mut store = docs_store(data_dir)?;
+    let mut authors = Vec::new();
+    for (name, id) in conf.list_keys(KeyTable::Authors).await? {
+        match store.get_author(&AuthorId::from(id))? {
+            Some(author) => authors.push((name, author.to_bytes())),
+            None => println!("author {name} is not in the docs store , skipped"),
+        }
+    }
+    let bundle = Bundle {
+        node: secret.to_bytes(),
+        docs: conf.list_keys(KeyTable::Docs).await?,
+        authors,
+    };
+    let pass = passphrase::read("bundle passphrase: ", passphrase::BUNDLE_ENV_VAR, true)?;
+    let plain = postcard::to_stdvec(&bundle)?;
+    let bundle_file = BundleFile {
+        version: BUNDLE_VERSION,
+        node: secret.public().to_string(),
+        locked: Locked::lock(&pass, &plain, BUNDLE_AAD)?,
+    };
+    fs::write(file, serde_json::to_vec_pretty(&bundle_file)?)?;
+    println!(
+        "exported {} with {} docs and {} authors to {}",
+        bundle_file.node,
+        bundle.docs.len(),
+        bundle.authors.len(),
+        file.display()
+    );
+    Ok(())
+}
+
+// The current node secret is kept as the previous one
+pub async fn import(conf: &ConfigClient, data_dir: &Path, file: &Path) -> Result<()> {
+    let text = fs::read(file).with_context(|| format!("can't read {}", file.display()))?;
+    let bundle_file: BundleFile = serde_json::from_slice(&text).context("not a bundle")?;
+    if bundle_file.version > BUNDLE_VERSION {
+        bail!("bundle version {} is too new", bundle_file.version);
+    }
+    let pass = passphrase::read("bundle passphrase: ", passphrase::BUNDLE_ENV_VAR, false)?;
+    let plain = bundle_file.locked.unlock(&pass, BUNDLE_AAD)?;
+    let bundle: Bundle = postcard::from_bytes(&plain).context("bad bundle contents")?;
+
+    let mut store = docs_store(data_dir)?;
+    for (name, secret) in &bundle.authors {
+        let author = Author::from_bytes(secret);
+        store.import_author(author.clone())?;
+        conf.set_key(KeyTable::Authors, name, author.id().to_bytes())
+            .await?;
+    }
+    store.flush()?;
+    for (name, id) in &bundle.docs {
+        conf.set_key(KeyTable::Docs, name, *id).await?;
+    }
+    let secret = SecretKey::from_bytes(&bundle.node);
+    conf.replace_secret_key(&secret).await?;
+    println!(
+        "imported {} with {} docs and {} authors",
+        secret.public(),
+        bundle.docs.len(),
+        bundle.authors.len()
+    );
+    Ok(())
+}
+
+// New node key , the old one signs the move for peers
+pub async fn rotate(conf: &ConfigClient) -> Result<()> {
+    let old = conf.get_secret_key().await?;
+    let new = SecretKey::generate(&mut rand::rng());
+    announce::store_rotation(conf, &old, &new).await?;
+    conf.replace_secret_key(&new).await?;
+    println!("rotated {} to {}", old.public(), new.public());
+    println!("the move is announced to peers for 30 days while the node runs");
+    Ok(())
+}
@@ -0,0 +1,241 @@
+// Import a folder of markdown files into the notebook.
+// Obsidian style vaults , straight off the disk or out of any collection.
+//
+// vault/Projects/liminal/Plan.md -> "Plan" in the folder "Projects/liminal"
+//
+// Wiki links keep working because the title index answers to both
+// [[Plan]] and [[Projects/liminal/Plan]]. Importing the same vault again
+// updates the notes with the same folder and title instead of doubling up.
+//
+// Hidden folders (.obsidian , .trash) are passed over quietly, anything
+// else that can't be brought in ends up in the report.
+
+use std::collections::HashMap;
+use std::fs::FileType;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, ensure};
+use bytes::Bytes;
+use iroh_blobs::{BlobsProtocol, Hash, format::collection::Collection};
+use tracing::warn;
+
+use crate::notes::{NoteLink, Notes};
+
+#[derive(Clone, Debug, Default)]
+pub struct ImportReport {
+    pub imported: Vec<NoteLink>,
+    // path and why
+    pub failed: Vec<(String, String)>,
+}
+
+// Everything under a directory on this node
+pub async fn import_dir(notes: &Notes, dir: &Path) -> Result<ImportReport> {
+    let mut importer = Importer::new(notes).await?;
+    let mut pending: Vec<PathBuf> = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = match read_entries(&current).await {
+            Ok(entries) => entries,
+            // the top has to be there , anything below just goes in the report
+            Err(e) if current == dir => return Err(e.into()),
+            Err(e) => {
+                importer.fail(&relative(dir, &current), e.to_string());
+                continue;
+            }
+        };
+        for (path, file_type) in entries {
+            let name = relative(dir, &path);
+            if is_hidden(&name) {
+                continue;
+            }
+            // could point anywhere , outside the import root too
+            if file_type.is_symlink() {
+                importer.fail(&name, "symlinks are not followed".to_string());
+                continue;
+            }
+            if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            match tokio::fs::read(&path).await {
+                Ok(data) => importer.add(&name, data.into()).await,
+                Err(e) => importer.fail(&name, e.to_string()),
+            }
+        }
+    }
+    Ok(importer.finish())
+}
+
+// A directory typed into the web ui , relative to the import root or
+// absolute , and it has to end up inside the root either way
+pub fn resolve_dir(root: &Path, dir: &str) -> Result<PathBuf> {
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("import root {} is missing", root.display()))?;
+    let path = root
+        .join(dir)
+        .canonicalize()
+        .with_context(|| format!("no directory {dir}"))?;
+    ensure!(path.starts_with(&root), "{dir} is outside the import root");
+    ensure!(path.is_dir(), "{dir} is not a directory");
+    Ok(path)
+}
+
+// one directory at a time , a bad entry fails that directory and not the import
+async fn read_entries(dir: &Path) -> std::io::Result<Vec<(PathBuf, FileType)>> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut found = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        found.push((entry.path(), entry.file_type().await?));
+    }
+    Ok(found)
+}
+
+// Everything in a collection , any layout
+pub async fn import_collection(
+    notes: &Notes,
+    blobs: &BlobsProtocol,
+    hash: Hash,
+) -> Result<ImportReport> {
+    let mut importer = Importer::new(notes).await?;
+    let coll = Collection::load(hash, blobs.store()).await?;
+    for (name, hash) in coll.iter() {
+        if is_hidden(name) {
+            continue;
+        }
+        match blobs.get_bytes(*hash).await {
+            Ok(data) => importer.add(name, data).await,
+            Err(e) => importer.fail(name, e.to_string()),
+        }
+    }
+    Ok(importer.finish())
+}
+
+struct Importer<'a> {
+    notes: &'a Notes,
+    // lower cased folder/title to id of the notes already here
+    existing: HashMap<String, String>,
+    report: ImportReport,
+}
+
+impl<'a> Importer<'a> {
+    async fn new(notes: &'a Notes) -> Result<Self> {
+        let existing = notes
+            .get_notes()
+            .await?
+            .into_iter()
+            .map(|n| (n.path().to_lowercase(), n.id))
+            .collect();
+        Ok(Self {
+            notes,
+            existing,
+            report: ImportReport::default(),
+        })
+    }
+
+    async fn add(&mut self, name: &str, data: Bytes) {
+        let Some((folder, title)) = split_name(name) else {
+            self.fail(name, "not a markdown file".to_string());
+            return;
+        };
+        let Ok(text) = String::from_utf8(data.to_vec()) else {
+            self.fail(name, "not utf-8 text".to_string());
+            return;
+        };
+        let path = match folder.is_empty() {
+            true => title.clone(),
+            false => format!("{folder}/{title}"),
+        };
+        let res = match self.existing.get(&path.to_lowercase()) {
+            Some(id) => self
+                .notes
+                .update_note(id.clone(), text)
+                .await
+                .map(|_| id.clone()),
+            None => self.notes.create_in(folder, title.clone(), text).await,
+        };
+        match res {
+            Ok(id) => {
+                self.existing.insert(path.to_lowercase(), id.clone());
+                self.report.imported.push(NoteLink {
+                    id,
+                    title: path,
+                    locked: false,
+                });
+            }
+            Err(e) => self.fail(name, e.to_string()),
+        }
+    }
+
+    fn fail(&mut self, name: &str, reason: String) {
+        warn!("import of {} failed , {}", name, reason);
+        self.report.failed.push((name.to_string(), reason));
+    }
+
+    fn finish(self) -> ImportReport {
+        self.report
+    }
+}
+
+// a/b/Note.md -> ("a/b", "Note") , None for anything that isn't markdown
+fn split_name(name: &str) -> Option<(String, String)> {
+    let name = name.trim_matches('/');
+    let (folder, file) = match name.rsplit_once('/') {
+        Some((folder, file)) => (folder, file),
+        None => ("", name),
+    };
+    let title = file.strip_suffix(".md")?.trim();
+    if title.is_empty() {
+        return None;
+    }
+    Some((folder.to_string(), title.to_string()))
+}
+
+fn is_hidden(name: &str) -> bool {
+    name.split('/').any(|part| part.starts_with('.'))
+}
+
+fn relative(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir).unwrap_or(path).display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirs_stay_under_the_root() {
+        let root = std::env::temp_dir().join(format!("liminal-import-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("vault/sub")).unwrap();
+        let canon = root.canonicalize().unwrap();
+
+        assert_eq!(resolve_dir(&root, "vault").unwrap(), canon.join("vault"));
+        assert_eq!(
+            resolve_dir(&root, "vault/sub/").unwrap(),
+            canon.join("vault/sub")
+        );
+        let absolute = canon.join("vault").display().to_string();
+        assert_eq!(resolve_dir(&root, &absolute).unwrap(), canon.join("vault"));
+
+        assert!(resolve_dir(&root, "..").is_err());
+        assert!(resolve_dir(&root, "vault/../..").is_err());
+        assert!(resolve_dir(&root, "/").is_err());
+        assert!(resolve_dir(&root, "missing").is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            split_name("a/b/Note.md"),
+            Some(("a/b".to_string(), "Note".to_string()))
+        );
+        assert_eq!(
+            split_name("Note.md"),
+            Some((String::new(), "Note".to_string()))
+        );
+        assert_eq!(split_name("picture.png"), None);
+        assert!(is_hidden(".obsidian/app.json"));
+        assert!(!is_hidden("Projects/Plan.md"));
+    }
+}
@@ -2,13 +2,10 @@
 ///! Using rocket and tokio
 ///! Testing ground docs,blobs and gossip
 ///! It should be a usefull interface
-use std::{
-    net::{Ipv4Addr, SocketAddrV4},
-    str::FromStr,
-};
+use std::{str::FromStr, time::Duration};
 
 use clap::Parser;
-use iroh::{Endpoint, EndpointId, RelayMode, SecretKey};
+use iroh::{Endpoint, EndpointId, SecretKey, discovery::static_provider::StaticProvider};
 use iroh_blobs::{ALPN as BLOBS_ALPN, Hash, store::fs::FsStore};
 use iroh_docs::{ALPN as DOCS_ALPN, AuthorId, protocol::Docs};
 use iroh_gossip::{
@@ -19,16 +16,30 @@ use iroh_tickets::endpoint::EndpointTicket;
 use n0_future::StreamExt;
 use n0_snafu::{Result, ResultExt, format_err};
 use n0_watcher::Watcher;
-use std::path::PathBuf;
+use rocket::data::{Limits, ToByteUnit};
 use tokio::signal::ctrl_c;
 
+mod access;
+mod admin;
+mod announce;
 mod cli;
 mod config;
+mod doc_store;
+mod front_matter;
+mod identity;
+mod import;
+mod markdown;
+mod node_config;
 // mod fren;
 mod notes;
+mod passphrase;
+mod publish;
+mod seal;
 mod replicate;
+mod schema;
 mod store;
 mod templates;
+mod trust;
 mod web;
 mod id_store;
 // mod finder;
@@ -49,27 +60,53 @@ async fn main() -> Result<()> {
 
     // parse the cli command
     let peers = match &args.command {
-        Command::Open => {
-            vec![]
-        }
         Command::Join { ticket } => {
             let Ticket { peers } = Ticket::from_str(ticket)?;
             peers
         }
+        _ => vec![],
+    };
+
+    // Node config file with env and cli overrides
+    let node_conf = match node_config::NodeConfig::load(&args) {
+        Ok(node_conf) => node_conf,
+        Err(e) => return Err(format_err!("{:#} bad config!", e)),
+    };
+    let relay_mode = match node_conf.relay_mode() {
+        Ok(mode) => mode,
+        Err(e) => return Err(format_err!("{:#}", e)),
     };
 
     // Config DB , anyhow vs snafu is weird
-    let db_res = config::Info::new(&PathBuf::from("data/config.db"));
-    let mut conf = match db_res {
-        Ok(conf) => conf,
+    let config_api = match config::ConfigApi::spawn(&node_conf.path("config.db")) {
+        Ok(api) => api,
         Err(e) => return Err(format_err!("{} bad database!", e)),
     };
+    let conf = config_api.client();
+
+    // Locked secrets have to be opened before anything reads them
+    if let Err(e) = unlock_secrets(&args, &conf).await {
+        return Err(format_err!("{:#}", e));
+    }
+
+    // Identity commands work on the files directly , run and exit
+    let offline = match &args.command {
+        Command::Export { file } => Some(identity::export(&conf, &node_conf.data_dir, file).await),
+        Command::Import { file } => Some(identity::import(&conf, &node_conf.data_dir, file).await),
+        Command::Rotate => Some(identity::rotate(&conf).await),
+        Command::LockSecrets => Some(lock_secrets(&args, &conf).await),
+        Command::UnlockSecrets => Some(conf.remove_lock().await),
+        command => admin::run(command, &conf, &node_conf).await,
+    };
+    if let Some(res) = offline {
+        return res.map_err(|e| format_err!("{:#}", e));
+    }
 
     // --random cli entry will generate a new node id
     // Or use a fixed one from config
     let secret_key = match &args.random {
         true => SecretKey::generate(&mut rand::rng()),
-        false => match conf.get_secret_key() {
+        false => match conf.get_secret_key().await {
             Ok(secret) => secret.to_owned(),
             Err(_) => return Err(format_err!("Bad secret")),
         },
@@ -77,10 +114,38 @@ async fn main() -> Result<()> {
 
 
 
+    // Create the ID manager
+    let id_manager = match id_store::IdentityApi::spawn(&node_conf.path("id.rdb")) {
+        Ok(api) => api,
+        Err(e) => return Err(format_err!("{:#} bad id database!", e)),
+    };
+    let id_client = id_manager.client();
+
+    // Peers from the ticket are worth remembering
+    for peer in &peers {
+        if let Err(e) = id_client.seen(peer.id, Some(peer.clone()), "ticket").await {
+            warn!("{:#}", e);
+        }
+    }
+
+    // Addresses from earlier sightings , so known peers can be
+    // dialed again without waiting on discovery
+    let known_peers = StaticProvider::new();
+    match id_client.addrs().await {
+        Ok(addrs) => {
+            for addr in addrs {
+                known_peers.add_endpoint_info(addr);
+            }
+        }
+        Err(e) => warn!("{:#}", e),
+    }
+
     // build our magic endpoint
     let endpoint = iroh::Endpoint::builder()
         .secret_key(secret_key.clone())
-        .relay_mode(RelayMode::Default)
+        .relay_mode(relay_mode)
+        .bind_addr_v4(node_conf.bind_addr())
+        .add_discovery(known_peers)
         .bind()
         .await
         .unwrap();
@@ -88,28 +153,55 @@ async fn main() -> Result<()> {
     // this needs to have a timeout
     endpoint.online().await;
 
-
-
-    // Create the ID manager
-    let id_manager = id_store::IdentityApi::spawn("data/id.rdb");
-    let id_client = id_manager.client();
-    id_client.new_fren(endpoint.id()).await;
-    let  node_list = id_client.list().await.unwrap();
-    println!("{:#?}",node_list);
+    if let Err(e) = id_client.new_fren(endpoint.id()).await {
+        warn!("{:#}", e);
+    }
+    match id_client.list().await {
+        Ok(node_list) => println!("{:#?}", node_list),
+        Err(e) => warn!("{:#}", e),
+    }
 
 
     let node_ticket = iroh_tickets::endpoint::EndpointTicket::new(endpoint.addr());
     println!("ticket \n\n {:?}", node_ticket.to_string());
 
+    if let Some(name) = &node_conf.name {
+        let _ = conf.set_setting(announce::NAME_SETTING, name).await;
+    }
+
     // Stash some nodes
-    let _ = conf.add_node(endpoint.id());
-    let _ = conf.list_nodes();
+    let _ = conf.add_node(endpoint.id()).await;
+    if let Ok(nodes) = conf.list_nodes().await {
+        for (node, status) in nodes {
+            println!("{} {}", node, status);
+        }
+    }
 
     // create the gossip protocol
     let gossip = Gossip::builder().spawn(endpoint.clone());
 
+    // Vouches that came in while we were away
+    if let Err(e) = trust::apply(&id_manager.client(), endpoint.id(), &node_conf.trust).await {
+        warn!("trust recompute failed {:#}", e);
+    }
+
+    // Tell peers about key rotations , vouches and our profile
+    let announcer = announce::Announcer::new(
+        gossip.clone(),
+        endpoint.clone(),
+        make_topic(&node_conf.topics.announce),
+        secret_key.clone(),
+        conf.clone(),
+        id_manager.client(),
+        node_conf.trust.clone(),
+        vec!["gossip".to_string(), "blobs".to_string(), "docs".to_string()],
+    );
+    if let Err(e) = announcer.spawn().await {
+        warn!("announcer failed to start {:#}", e);
+    }
+
     // BLOBS!
-    let path = PathBuf::from("data/blobs");
+    let path = node_conf.path("blobs");
     let store = FsStore::load(path).await.unwrap();
     let blobs = iroh_blobs::BlobsProtocol::new(&store, None);
     
@@ -118,7 +210,9 @@ async fn main() -> Result<()> {
     let fileset = store::FileSet::new(blobs.clone());
     // TODO make this prettier.
     // Get the file roots
-    fileset.fill("col").await;
+    for prefix in &node_conf.replicate.collections {
+        fileset.fill(prefix).await;
+    }
     // fileset.fill("archive").await;
     // fileset.fill("notes").await;
 
@@ -126,12 +220,12 @@ async fn main() -> Result<()> {
     //fileset.del_tags("col-17").await.unwrap();
 
         // Create the finder
-    let finder_topic = make_topic("finder");
+    let finder_topic = make_topic(&node_conf.topics.finder);
     let finder = Finder::new(finder_topic,blobs.clone(),gossip.clone(),vec![],secret_key.clone());
     finder.spawn().await;
     
     // DOCS !
-    let docs_path = PathBuf::from("data/");
+    let docs_path = node_conf.data_dir.clone();
     let docs = Docs::persistent(docs_path)
         .spawn(endpoint.clone(), (*blobs).clone(), gossip.clone())
         .await
@@ -148,9 +242,36 @@ async fn main() -> Result<()> {
 
     // setup router
     let router = iroh::protocol::Router::builder(endpoint.clone())
-        .accept(GOSSIP_ALPN, gossip.clone())
-        .accept(BLOBS_ALPN, blobs.clone())
-        .accept(DOCS_ALPN, docs.clone())
+        .accept(
+            GOSSIP_ALPN,
+            access::Guarded::new(
+                "gossip",
+                gossip.clone(),
+                node_conf.access.gossip.clone(),
+                id_manager.client(),
+                endpoint.clone(),
+            ),
+        )
+        .accept(
+            BLOBS_ALPN,
+            access::Guarded::new(
+                "blobs",
+                blobs.clone(),
+                node_conf.access.blobs.clone(),
+                id_manager.client(),
+                endpoint.clone(),
+            ),
+        )
+        .accept(
+            DOCS_ALPN,
+            access::Guarded::new(
+                "docs",
+                docs.clone(),
+                node_conf.access.docs.clone(),
+                id_manager.client(),
+                endpoint.clone(),
+            ),
+        )
         // .accept(FREN_ALPN, fren_api.expose().unwrap())
         .spawn();
 
@@ -158,24 +279,38 @@ async fn main() -> Result<()> {
     // Stores the doc id in the config and makes a new one
     // if it is not there.
     // create a base author (not default )
-    let base_author = match conf.get_notes_author() {
+    let base_author = match conf.get_notes_author().await {
         Ok(id) => AuthorId::from(id),
         Err(_) => {
             let new_author = docs.author_create().await.unwrap();
-            let _ = conf.set_author_key("notes", new_author.to_bytes());
+            let _ = conf.set_author_key("notes", new_author.to_bytes()).await;
             new_author
         }
     };
 
-    let base_notes = match conf.get_notes_id() {
-        Ok(id) => notes::Notes::from_id(id, base_author, blobs.clone(), docs.clone())
-            .await
-            .unwrap(),
+    let base_notes = match conf.get_notes_id().await {
+        Ok(id) => notes::Notes::from_id(
+            id,
+            base_author,
+            blobs.clone(),
+            docs.clone(),
+            endpoint.clone(),
+            node_conf.notes.trash_days,
+        )
+        .await
+        .unwrap(),
         Err(_) => {
-            let n = notes::Notes::new(None, base_author, blobs.clone(), docs.clone())
-                .await
-                .unwrap();
-            let _ = conf.set_docs_key("notes", n.id());
+            let n = notes::Notes::new(
+                None,
+                base_author,
+                blobs.clone(),
+                docs.clone(),
+                endpoint.clone(),
+                node_conf.notes.trash_days,
+            )
+            .await
+            .unwrap();
+            let _ = conf.set_docs_key("notes", n.id()).await;
             n
         }
     };
@@ -186,26 +321,25 @@ async fn main() -> Result<()> {
     // let _e = base_notes.share().await;
     let _e = base_notes.run().await;
 
-    // let val = base_notes.delete_hidden().await;
-    // println!("{:#?}", val);
-
-    let val = base_notes.bounce_down().await;
-    println!("{:#?}", val);
-
-    // let val = base_notes.bounce_up("notes-1759074698").await;
-    // println!("{:#?}", val);
+    // Snapshot the notes , skipped when nothing changed
+    let snapshot_hours = node_conf.notes.snapshot_hours;
+    match snapshot_hours.checked_mul(3600) {
+        Some(0) => info!("notes snapshots are off"),
+        Some(secs) => base_notes.schedule_snapshots(Duration::from_secs(secs)),
+        None => warn!("snapshot_hours {} is too big , snapshots are off", snapshot_hours),
+    }
 
     // Set liminal, hashed as the topic
 
     let peer_ids = vec![];
-    let topic =make_topic("liminal::");
+    let topic = make_topic(&node_conf.topics.replicate);
     let repl = replicate::Replicator::new(
         gossip.clone(),
         blobs.clone(),
         topic,
         peer_ids,
         secret_key.clone(),
-        vec!["col".to_string(), "notes".to_string(),"archive".to_string()],
+        node_conf.replicate.prefixes.clone(),
     )
     .await?;
     repl.run().await?;
@@ -218,13 +352,21 @@ async fn main() -> Result<()> {
     // Web interface
     // println!("{}", node_ticket);
     if args.web {
-        let rocket_secret_key: [u8; 32] = conf.rocket_key().unwrap();
+        let rocket_secret_key: [u8; 32] = conf.rocket_key().await.unwrap();
         println!("starting web server ");
         // start the web server
         let figment = rocket::Config::figment()
-            .merge(("address", "0.0.0.0"))
-            .merge(("port", 8080))
+            .merge(("address", node_conf.web.address.clone()))
+            .merge(("port", node_conf.web.port))
             .merge(("secret_key", rocket_secret_key))
+            // room for large notes and attachments
+            .merge((
+                "limits",
+                Limits::default()
+                    .limit("form", 4.mebibytes())
+                    .limit("file", 64.mebibytes())
+                    .limit("data-form", 64.mebibytes()),
+            ))
             .merge(("log_level", "critical"))
             .merge(("cli_colors", "false"));
 
@@ -234,12 +376,17 @@ async fn main() -> Result<()> {
             .manage(blobs.clone())
             .manage(endpoint.clone())
             .manage(docs.clone())
+            .manage(conf.clone())
+            .manage(id_manager.client())
+            .manage(web::feeds::FeedKey::new(&rocket_secret_key))
+            .manage(web::notes::ImportRoot(node_conf.web.import_root.clone()))
             .register("/", catchers![web::auth::unauthorized])
             .attach(web::stage())
             .attach(web::assets::stage())
             .attach(web::services::stage())
             .attach(web::notes::stage())
             .attach(web::replica::stage())
+            .attach(web::feeds::stage())
             .launch()
             .await;
     } else {
@@ -252,6 +399,38 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+// Keyfile , env passphrase or the --unlock prompt
+async fn unlock_secrets(args: &cli::Args, conf: &config::ConfigClient) -> anyhow::Result<()> {
+    if !conf.is_locked().await? {
+        return Ok(());
+    }
+    let with = if let Some(path) = &args.keyfile {
+        config::Unlock::Keyfile(std::fs::read(path)?)
+    } else if args.unlock || std::env::var(passphrase::ENV_VAR).is_ok() {
+        let pass = passphrase::read("secrets passphrase: ", passphrase::ENV_VAR, false)?;
+        config::Unlock::Passphrase(pass)
+    } else {
+        anyhow::bail!(
+            "secrets are locked , start with --unlock, --keyfile or {}",
+            passphrase::ENV_VAR
+        );
+    };
+    conf.open_secrets(with).await
+}
+
+async fn lock_secrets(args: &cli::Args, conf: &config::ConfigClient) -> anyhow::Result<()> {
+    let with = match &args.keyfile {
+        Some(path) => config::Unlock::Keyfile(std::fs::read(path)?),
+        None => {
+            let pass = passphrase::read("new secrets passphrase: ", passphrase::ENV_VAR, true)?;
+            config::Unlock::Passphrase(pass)
+        }
+    };
+    conf.lock_secrets(with).await?;
+    println!("secrets locked");
+    Ok(())
+}
+
 pub fn make_topic(name: &str )-> TopicId { 
     TopicId::from_bytes(*Hash::new(name).as_bytes())
 }
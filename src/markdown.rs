@@ -0,0 +1,284 @@
+// Markdown to html for notes.
+// Links are handed to a resolver so the web interface and the
+// published site can point them at different places.
+//
+// liminal:<hash> links and images are attachments
+// [[Title]] wiki links point at other notes
+// - [ ] task checkboxes can be swapped for something clickable
+//
+// Notes arrive from other peers , so the html that comes out the
+// end is cleaned against an allowlist before it goes anywhere near
+// a page. Fenced code blocks are highlighted on the server with
+// class names , the colours come from highlight_css().
+
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use ammonia::Builder;
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, LinkType, Options, Parser, Tag, TagEnd};
+use syntect::html::{ClassStyle, ClassedHTMLGenerator, css_for_theme_with_class_style};
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+const THEME: &str = "InspiredGitHub";
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+static CLEANER: LazyLock<Builder<'static>> = LazyLock::new(|| {
+    let mut builder = Builder::default();
+    builder
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .link_rel(Some("noopener noreferrer nofollow"))
+        // highlighted code
+        .add_tag_attributes("span", &["class"])
+        .add_tag_attributes("pre", &["class"])
+        .add_tag_attributes("code", &["class"])
+        // task checkboxes
+        .add_tags(&["input"])
+        .add_tag_attributes("input", &["checked", "disabled", "class"])
+        .add_tag_attribute_values("input", "type", &["checkbox"])
+        .add_generic_attribute_prefixes(&["data-task-"]);
+    builder
+});
+
+pub trait LinkResolver {
+    // liminal:<hash>
+    fn attachment(&self, hash: &str) -> String;
+    // [[target]] , target is whatever is inside the brackets
+    fn wiki(&self, target: &str) -> String;
+    // html for a task checkbox on a (1 based) line , None for the plain one
+    fn task(&self, _line: usize, _checked: bool) -> Option<String> {
+        None
+    }
+}
+
+// A - [ ] item , line is 1 based within the text that was parsed
+#[derive(Clone, Debug)]
+pub struct Task {
+    pub line: usize,
+    pub checked: bool,
+    pub text: String,
+}
+
+pub fn options() -> Options {
+    Options::ENABLE_WIKILINKS | Options::ENABLE_TASKLISTS
+}
+
+pub fn render(text: &str, links: &impl LinkResolver) -> String {
+    let mut code: Option<(String, String)> = None;
+    let parser = Parser::new_ext(text, options())
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            // gather up fenced code and swap it for the highlighted version
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) => {
+                code = Some((lang.to_string(), String::new()));
+                None
+            }
+            Event::Text(inner) if code.is_some() => {
+                if let Some((_, buf)) = code.as_mut() {
+                    buf.push_str(&inner);
+                }
+                None
+            }
+            Event::End(TagEnd::CodeBlock) if code.is_some() => {
+                let (lang, buf) = code.take().unwrap_or_default();
+                Some(Event::Html(highlight(&lang, &buf).into()))
+            }
+            Event::TaskListMarker(checked) => match links.task(line_of(text, range.start), checked) {
+                Some(html) => Some(Event::InlineHtml(html.into())),
+                None => Some(event),
+            },
+            event => Some(resolve(links, event)),
+        });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    CLEANER.clean(&html).to_string()
+}
+
+// Stylesheet for the highlighted code blocks
+pub fn highlight_css() -> String {
+    let themes = ThemeSet::load_defaults();
+    match themes.themes.get(THEME) {
+        Some(theme) => css_for_theme_with_class_style(theme, CLASS_STYLE).unwrap_or_default(),
+        None => String::new(),
+    }
+}
+
+fn highlight(lang: &str, code: &str) -> String {
+    // info strings can carry more than the language
+    let lang = lang.split_whitespace().next().unwrap_or_default();
+    let syntax = SYNTAXES
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        if generator.parse_html_for_line_which_includes_newline(line).is_err() {
+            return format!("<pre><code>{}</code></pre>", ammonia::clean_text(code));
+        }
+    }
+    format!("<pre class=\"hl-code\"><code>{}</code></pre>", generator.finalize())
+}
+
+// Every task item in the text , code blocks are skipped by the parser
+pub fn tasks(text: &str) -> Vec<Task> {
+    let mut tasks = Vec::new();
+    for (event, range) in Parser::new_ext(text, options()).into_offset_iter() {
+        if let Event::TaskListMarker(checked) = event {
+            let line = line_of(text, range.start);
+            let content = text.lines().nth(line - 1).unwrap_or_default();
+            let content = match content.split_once(']') {
+                Some((_, rest)) => rest.trim(),
+                None => content.trim(),
+            };
+            tasks.push(Task {
+                line,
+                checked,
+                text: content.to_string(),
+            });
+        }
+    }
+    tasks
+}
+
+// Flip the task on a line , None if there is no task there
+// or it is not in the state the caller saw.
+pub fn toggle_task(text: &str, line: usize, checked: bool) -> Option<String> {
+    tasks(text)
+        .iter()
+        .find(|t| t.line == line && t.checked == checked)?;
+    let mut out = String::with_capacity(text.len());
+    for (index, current) in text.split_inclusive('\n').enumerate() {
+        if index + 1 == line {
+            let (from, to) = match checked {
+                true => (if current.contains("[x]") { "[x]" } else { "[X]" }, "[ ]"),
+                false => ("[ ]", "[x]"),
+            };
+            out.push_str(&current.replacen(from, to, 1));
+        } else {
+            out.push_str(current);
+        }
+    }
+    Some(out)
+}
+
+// [[Note#Heading]] and [[Note.md]] both point at Note
+pub fn wiki_target(target: &str) -> &str {
+    let target = target.split('#').next().unwrap_or_default().trim();
+    target.strip_suffix(".md").unwrap_or(target)
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+fn resolve<'a>(links: &impl LinkResolver, event: Event<'a>) -> Event<'a> {
+    let local = |dest_url: CowStr<'a>| -> CowStr<'a> {
+        match dest_url.strip_prefix("liminal:") {
+            Some(hash) => links.attachment(hash).into(),
+            None => dest_url,
+        }
+    };
+    match event {
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: local(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Link {
+            link_type: link_type @ LinkType::WikiLink { .. },
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: links.wiki(&dest_url).into(),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: local(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Links;
+
+    impl LinkResolver for Links {
+        fn attachment(&self, hash: &str) -> String {
+            format!("/notes/attachment/n1/{hash}")
+        }
+
+        fn wiki(&self, target: &str) -> String {
+            format!("/notes/{}", wiki_target(target))
+        }
+    }
+
+    #[test]
+    fn attachment_images_point_at_the_route() {
+        let html = render("![cat](liminal:abc123)", &Links);
+        assert!(html.contains("src=\"/notes/attachment/n1/abc123\""), "{html}");
+        assert!(html.contains("alt=\"cat\""), "{html}");
+    }
+
+    #[test]
+    fn attachment_links_point_at_the_route() {
+        let html = render("[the file](liminal:abc123)", &Links);
+        assert!(html.contains("href=\"/notes/attachment/n1/abc123\""), "{html}");
+        assert!(!html.contains("liminal:"), "{html}");
+    }
+
+    #[test]
+    fn other_links_are_left_alone() {
+        let html = render("[site](https://example.com/x.png)", &Links);
+        assert!(html.contains("href=\"https://example.com/x.png\""), "{html}");
+    }
+
+    #[test]
+    fn script_tags_are_stripped() {
+        let html = render("hi <script>alert(1)</script> there", &Links);
+        assert!(!html.contains("<script"), "{html}");
+        assert!(html.contains("hi"), "{html}");
+    }
+
+    #[test]
+    fn script_and_data_links_are_dropped() {
+        let html = render("[a](javascript:alert(1)) [b](data:text/html;base64,PHA+)", &Links);
+        assert!(!html.contains("javascript:"), "{html}");
+        assert!(!html.contains("data:"), "{html}");
+    }
+
+    #[test]
+    fn event_attributes_are_removed() {
+        let html = render("<img src=\"https://example.com/x.png\" onerror=\"alert(1)\">", &Links);
+        assert!(!html.contains("onerror"), "{html}");
+        assert!(html.contains("src=\"https://example.com/x.png\""), "{html}");
+    }
+
+    #[test]
+    fn code_blocks_are_escaped() {
+        let html = render("```\n<script>alert(1)</script>\n```\n", &Links);
+        assert!(!html.contains("<script"), "{html}");
+        assert!(html.contains("&lt;script&gt;"), "{html}");
+    }
+}
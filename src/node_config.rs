@@ -0,0 +1,244 @@
+//! Node configuration from a toml file.
+//! Paths , ports and topics that used to be hardcoded in main.
+//! Order is defaults < liminal.toml < LIMINAL_* env < cli flags ,
+//! clap handles the env vars so the cli and env share one override.
+//! Several nodes on one host just need their own data dir and ports.
+
+use std::{
+    fs,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, Result, bail};
+use iroh::{RelayMode, RelayUrl};
+use serde::{Deserialize, Serialize};
+
+use crate::access::Policy;
+use crate::cli::Args;
+use crate::trust::TrustConfig;
+
+// Used when --config is not given and it exists
+pub const DEFAULT_FILE: &str = "liminal.toml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    // everything the node keeps lives under here
+    pub data_dir: PathBuf,
+    // nickname
+    pub name: Option<String>,
+    pub web: WebConfig,
+    pub iroh: IrohConfig,
+    pub topics: TopicConfig,
+    pub replicate: ReplicateConfig,
+    pub access: AccessConfig,
+    pub trust: TrustConfig,
+    pub notes: NotesConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebConfig {
+    pub address: String,
+    pub port: u16,
+    // the notes import reads directories under here , unset turns it off
+    pub import_root: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IrohConfig {
+    // 0 picks a random port
+    pub bind_port: u16,
+    // default , staging , disabled or a relay url
+    pub relay: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TopicConfig {
+    // names get hashed into gossip topics
+    pub replicate: String,
+    pub finder: String,
+    // signed announcements , rotations and the like
+    pub announce: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicateConfig {
+    // tag prefixes that get shared
+    pub prefixes: Vec<String>,
+    // tag prefixes loaded into the file browser
+    pub collections: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotesConfig {
+    // how long a purged note stays in the trash
+    pub trash_days: u64,
+    // 0 turns the snapshots off
+    pub snapshot_hours: u64,
+}
+
+// Who gets in , per protocol and id store status
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    pub gossip: Policy,
+    pub blobs: Policy,
+    pub docs: Policy,
+}
+
+impl Default for AccessConfig {
+    fn default() -> Self {
+        Self {
+            gossip: Policy::default(),
+            blobs: Policy::limited(),
+            docs: Policy::limited(),
+        }
+    }
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from("data"),
+            name: None,
+            web: WebConfig::default(),
+            iroh: IrohConfig::default(),
+            topics: TopicConfig::default(),
+            replicate: ReplicateConfig::default(),
+            access: AccessConfig::default(),
+            trust: TrustConfig::default(),
+            notes: NotesConfig::default(),
+        }
+    }
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            address: "0.0.0.0".to_string(),
+            port: 8080,
+            import_root: None,
+        }
+    }
+}
+
+impl Default for IrohConfig {
+    fn default() -> Self {
+        Self {
+            bind_port: 0,
+            relay: "default".to_string(),
+        }
+    }
+}
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        Self {
+            replicate: "liminal::".to_string(),
+            finder: "finder".to_string(),
+            announce: "liminal::".to_string(),
+        }
+    }
+}
+
+impl Default for ReplicateConfig {
+    fn default() -> Self {
+        Self {
+            prefixes: vec!["col".to_string(), "notes".to_string(), "archive".to_string()],
+            collections: vec!["col".to_string()],
+        }
+    }
+}
+
+impl Default for NotesConfig {
+    fn default() -> Self {
+        Self {
+            trash_days: 30,
+            snapshot_hours: 24,
+        }
+    }
+}
+
+impl NodeConfig {
+    // The file from --config has to exist , the default one is optional
+    pub fn load(args: &Args) -> Result<Self> {
+        let mut conf = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None => match Path::new(DEFAULT_FILE).exists() {
+                true => Self::from_file(Path::new(DEFAULT_FILE))?,
+                false => Self::default(),
+            },
+        };
+        conf.apply(args);
+        conf.relay_mode()?;
+        Ok(conf)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("can't read config {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("bad config {}", path.display()))
+    }
+
+    // cli and env overrides , only the ones that were given
+    fn apply(&mut self, args: &Args) {
+        if let Some(data_dir) = &args.data_dir {
+            self.data_dir = data_dir.clone();
+        }
+        if let Some(name) = &args.name {
+            self.name = Some(name.clone());
+        }
+        if let Some(address) = &args.web_address {
+            self.web.address = address.clone();
+        }
+        if let Some(port) = args.web_port {
+            self.web.port = port;
+        }
+        if let Some(port) = args.bind_port {
+            self.iroh.bind_port = port;
+        }
+        if let Some(relay) = &args.relay {
+            self.iroh.relay = relay.clone();
+        }
+        if let Some(topic) = &args.topic {
+            self.topics.replicate = topic.clone();
+        }
+        if !args.prefix.is_empty() {
+            self.replicate.prefixes = args.prefix.clone();
+        }
+        if let Some(days) = args.trash_days {
+            self.notes.trash_days = days;
+        }
+        if let Some(hours) = args.snapshot_hours {
+            self.notes.snapshot_hours = hours;
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.data_dir.join(name)
+    }
+
+    pub fn bind_addr(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, self.iroh.bind_port)
+    }
+
+    pub fn relay_mode(&self) -> Result<RelayMode> {
+        let mode = match self.iroh.relay.as_str() {
+            "default" => RelayMode::Default,
+            "staging" => RelayMode::Staging,
+            "disabled" => RelayMode::Disabled,
+            url => match RelayUrl::from_str(url) {
+                Ok(url) => RelayMode::Custom(url.into()),
+                Err(_) => bail!("relay must be default, staging, disabled or a url not {url}"),
+            },
+        };
+        Ok(mode)
+    }
+}
@@ -3,85 +3,416 @@
 // With alterations...
 // https://github.com/n0-computer/iroh-examples/blob/main/tauri-todos/src-tauri/src/todos.rs
 
-// It turns out that iroh-docs uses a _weird_ prefix key system (for shared docs apparently)
-// This means that if you write a key that is a prefix of any other it will destroy keys
-// I don't think this is sane... but woteva.
+// Keys and values go through DocStore , which deals with the
+// iroh-docs prefix deletes (see doc_store.rs).
 
-// This means that all keys in the docs system need to have a delimiter at the end
-// so they don't prefix
-// when writing keys it appending a null byte on the end will fix this
-// as  per https://github.com/n0-computer/iroh-docs/issues/55
-// So ... when keys are written or read they need to have a null byte added
-// or removed as they come in and out of docs. Insane...
-
-use std::{cmp::Reverse, str::FromStr, sync::Arc};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::{Context, Result, anyhow, bail, ensure};
+use anyhow::{Context, Result, anyhow, bail};
 use bytes::Bytes;
-use chrono::{Local, Utc};
-use iroh_blobs::{BlobsProtocol, format::collection::Collection};
+use chrono::{DateTime, Local, Utc};
+use iroh::{Endpoint, EndpointId};
+use iroh_blobs::{BlobsProtocol, Hash, HashAndFormat, format::collection::Collection};
 use iroh_docs::{
-    AuthorId, DocTicket, Entry,
-    api::{
-        Doc,
-        protocol::{AddrInfoOptions, ShareMode},
-    },
-    engine::LiveEvent,
+    AuthorId, DocTicket,
+    api::protocol::{AddrInfoOptions, ShareMode},
     protocol::Docs,
-    store::Query,
+    store::DownloadPolicy,
 };
 
 use n0_future::{Stream, StreamExt, task};
 use serde::{Deserialize, Serialize};
-use tracing::{error, warn};
+use data_encoding::BASE64;
+use serde_json::Value;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+use crate::doc_store::{self, Change, DocStore, Record, Versioned};
+use crate::front_matter::FrontMatter;
+use crate::markdown::{self, Task};
+use crate::seal::{SealKey, Sealed};
 
 // Individual notes
+// The id is the doc key and never changes, older notes used the
+// cleaned up title as the id and have an empty title.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Note {
     pub id: String,
+    #[serde(default)]
+    pub title: String,
+    // titles from before a rename without relinking , [[Old]] still finds the note
+    #[serde(default)]
+    pub old_titles: Vec<String>,
     pub text: String,
     pub created: i64,
     pub updated: i64,
     pub is_delete: bool,
+    // trash info , purged notes are removed from the doc
+    // once they have been deleted for long enough
+    #[serde(default)]
+    pub deleted_at: i64,
+    #[serde(default)]
+    pub deleted_by: Option<String>,
+    #[serde(default)]
+    pub is_purge: bool,
+    // retention counts from here , not from the delete
+    #[serde(default)]
+    pub purged_at: i64,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    // big texts live in their own blob , see BODY_PREFIX
+    #[serde(default)]
+    pub body: Option<Hash>,
+    // parsed from the top of the text on save
+    #[serde(default)]
+    pub meta: FrontMatter,
+    // slash separated namespace , set by imports
+    #[serde(default)]
+    pub folder: String,
+    // title and text are sealed with the author key , see seal.rs
+    #[serde(default)]
+    pub private: bool,
+    #[serde(default)]
+    pub sealed: Option<Sealed>,
+    // private and not ours , only the outer fields are readable
+    #[serde(skip)]
+    pub locked: bool,
+    // content hasn't come down from a peer yet
+    #[serde(skip)]
+    pub pending: bool,
+}
+
+// The sealed part of a private note.
+// Attachment names go in here too , the files themselves can't be sealed
+// so private notes don't take new ones.
+#[derive(Serialize, Deserialize)]
+struct PrivateBody {
+    title: String,
+    text: String,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+// Files hung off a note, the bytes live in the blob store and
+// each one also gets its own doc entry (see Side) so it syncs with the notebook.
+// Referenced in the markdown as liminal:<hash>
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Attachment {
+    pub name: String,
+    pub hash: Hash,
+    pub size: u64,
+    // filled in when a note is opened
+    #[serde(skip)]
+    pub pending: bool,
+}
+
+// Attachment doc keys , the dot can't appear in a note id
+const ATTACHMENT_PREFIX: &str = ".attachment/";
+
+// Large note bodies.
+// Texts over INLINE_TEXT_LEN are written to a blob and the note entry only
+// carries the hash. A .body/<id> entry points at the blob so it syncs.
+// Older nodes still parse the note entry and show BODY_PLACEHOLDER as the text.
+const BODY_PREFIX: &str = ".body/";
+const INLINE_TEXT_LEN: usize = 8 * 1024;
+const BODY_PLACEHOLDER: &str =
+    "This note is stored as a separate blob, a newer version of liminal is needed to read it.";
+const BODY_MISSING: &str = "This note has not finished downloading yet.";
+const BODY_BROKEN: &str = "This note's body could not be read.";
+
+// What a side entry (.body/ and .attachment/) holds.
+// Nodes from before side entries decode every entry in the doc as a note ,
+// this reads to them as a deleted one so they skip it instead of failing
+// the whole list. Bodies carry the text , base64 when sealed. Attachments
+// carry the hash of the file , fetched once the entry has come down.
+#[derive(Serialize, Deserialize)]
+struct Side {
+    id: String,
+    text: String,
+    created: i64,
+    updated: i64,
+    is_delete: bool,
+    #[serde(default)]
+    hash: Option<Hash>,
 }
 
+impl Side {
+    fn new(key: &str, text: String, hash: Option<Hash>) -> Self {
+        Self {
+            id: key.to_string(),
+            text,
+            created: 0,
+            updated: 0,
+            is_delete: true,
+            hash,
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).context("invalid side entry")
+    }
+}
+
+// What everyone else sees of a private note
+const PRIVATE_TITLE: &str = "Private note";
+const PRIVATE_TEXT: &str = "This note is private.";
+
+// How long to wait on a peer for lazily fetched content
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Id to title map in the bounce down collection
+const TITLES_NAME: &str = ".titles.json";
+
+// Bounce down collection tags
+const SNAPSHOT_PREFIX: &str = "notes-";
+
 const MAX_NOTE_SIZE: usize = 16 * 1024;
-const MAX_TEXT_LEN: usize = 16 * 1000;
+const MAX_TEXT_LEN: usize = 4 * 1024 * 1024;
 
-impl Note {
-    fn from_bytes(bytes: Bytes) -> anyhow::Result<Self> {
-        let note = serde_json::from_slice(&bytes).context("invalid json")?;
+// Version 0 is everything written before the doc store
+impl Versioned for Note {
+    const VERSION: u32 = 1;
+    const MAX_SIZE: usize = MAX_NOTE_SIZE;
+
+    fn decode(_version: u32, value: Value) -> Result<Self> {
+        let mut note: Note = serde_json::from_value(value).context("invalid note")?;
+        // notes from before front matter
+        if note.meta.is_empty() {
+            note.meta = FrontMatter::parse(&note.text).0;
+        }
         Ok(note)
     }
+}
 
-    fn as_bytes(&self) -> anyhow::Result<Bytes> {
-        let buf = serde_json::to_vec(self)?;
-        ensure!(buf.len() < MAX_NOTE_SIZE, "todo too large");
-        Ok(buf.into())
+impl Note {
+    // Markdown without the front matter
+    pub fn body(&self) -> &str {
+        FrontMatter::parse(&self.text).1
+    }
+
+    pub fn title(&self) -> &str {
+        if self.locked || self.sealed.is_some() {
+            return PRIVATE_TITLE;
+        }
+        if !self.title.is_empty() {
+            return &self.title;
+        }
+        match &self.meta.title {
+            Some(title) => title,
+            None => &self.id,
+        }
+    }
+
+    // folder/title , just the title outside a folder
+    pub fn path(&self) -> String {
+        match self.folder.is_empty() {
+            true => self.title().to_string(),
+            false => format!("{}/{}", self.folder, self.title()),
+        }
+    }
+
+    pub fn deleted_time(&self) -> String {
+        format_time(self.deleted_at)
+    }
+
+    // Fine to copy out into snapshots and the site , not sealed and not a
+    // placeholder for content that hasn't come down yet
+    pub fn is_shareable(&self) -> bool {
+        !self.private && !self.locked && !self.pending
+    }
+
+    // When the sweep is allowed to remove it
+    pub fn purge_time(&self, trash_days: u64) -> String {
+        format_time(self.purge_after(trash_days))
+    }
+
+    // Purges from older nodes have no purged_at , fall back to the delete
+    fn purge_after(&self, trash_days: u64) -> i64 {
+        let from = match self.purged_at {
+            0 => self.deleted_at,
+            at => at,
+        };
+        from + trash_secs(trash_days)
+    }
+
+    fn link(&self) -> NoteLink {
+        NoteLink {
+            id: self.id.clone(),
+            title: self.title().to_string(),
+            locked: self.locked,
+        }
     }
 
     fn missing_note(id: String) -> Self {
         Self {
+            title: String::new(),
+            old_titles: vec![],
             text: String::from(""),
             created: 0,
             updated: 0,
             is_delete: false,
+            deleted_at: 0,
+            deleted_by: None,
+            is_purge: false,
+            purged_at: 0,
+            attachments: vec![],
+            body: None,
+            meta: FrontMatter::default(),
+            folder: String::new(),
+            private: false,
+            sealed: None,
+            locked: false,
+            pending: false,
             id,
         }
     }
 
     pub fn bad_note() -> Self {
         Self {
+            title: String::from("bad_note"),
+            old_titles: vec![],
             text: String::from("bad_note"),
             created: 0,
             updated: 0,
             is_delete: false,
+            deleted_at: 0,
+            deleted_by: None,
+            is_purge: false,
+            purged_at: 0,
+            attachments: vec![],
+            body: None,
+            meta: FrontMatter::default(),
+            folder: String::new(),
+            private: false,
+            sealed: None,
+            locked: false,
+            pending: false,
             id: String::from("bad_note"),
         }
     }
 }
 
-// All the keys need to be null byte extended , ? I know right
+// How much of the notebook comes down from peers.
+// Sits on the doc as an iroh-docs download policy , so each notebook
+// has its own and it survives restarts. Anything left behind is
+// fetched when the note is opened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Download {
+    Everything,
+    NoAttachments,
+    // note entries only , big bodies and attachments on open
+    NotesOnly,
+    // only the notes that have been opened on this node
+    OnDemand,
+}
+
+impl Download {
+    pub const ALL: [Download; 4] = [
+        Download::Everything,
+        Download::NoAttachments,
+        Download::NotesOnly,
+        Download::OnDemand,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Download::Everything => "everything",
+            Download::NoAttachments => "no-attachments",
+            Download::NotesOnly => "notes-only",
+            Download::OnDemand => "on-demand",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Download::Everything => "Everything",
+            Download::NoAttachments => "Everything except attachments",
+            Download::NotesOnly => "Notes only",
+            Download::OnDemand => "Only notes opened here",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Download::ALL.into_iter().find(|d| d.name() == name)
+    }
+
+    fn policy(&self) -> DownloadPolicy {
+        let attachments = doc_store::prefix_filter(ATTACHMENT_PREFIX);
+        let bodies = doc_store::prefix_filter(BODY_PREFIX);
+        match self {
+            Download::Everything => DownloadPolicy::EverythingExcept(vec![]),
+            Download::NoAttachments => DownloadPolicy::EverythingExcept(vec![attachments]),
+            Download::NotesOnly => DownloadPolicy::EverythingExcept(vec![attachments, bodies]),
+            Download::OnDemand => DownloadPolicy::NothingExcept(vec![]),
+        }
+    }
+
+    fn from_policy(policy: &DownloadPolicy) -> Self {
+        match policy {
+            DownloadPolicy::NothingExcept(_) => Download::OnDemand,
+            DownloadPolicy::EverythingExcept(filters) => {
+                if filters.contains(&doc_store::prefix_filter(BODY_PREFIX)) {
+                    Download::NotesOnly
+                } else if filters.contains(&doc_store::prefix_filter(ATTACHMENT_PREFIX)) {
+                    Download::NoAttachments
+                } else {
+                    Download::Everything
+                }
+            }
+        }
+    }
+}
+
+// For the menus , id to link to and title to show
+#[derive(Clone, Debug)]
+pub struct NoteLink {
+    pub id: String,
+    pub title: String,
+    pub locked: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub tag: String,
+    pub hash: Hash,
+    pub created: i64,
+}
+
+impl Snapshot {
+    pub fn created_time(&self) -> String {
+        format_time(self.created)
+    }
+}
+
+// Stale edits
+// The edit form carries the content hash of the entry it was loaded from,
+// if the head has moved on by the time it is saved we hand back all three
+// versions and a merge attempt instead of writing.
+// The check and the write happen under Inner.edits so two saves on this
+// node can't both pass the check. Peers writing at the same moment are
+// still settled by the doc , newest entry wins.
+#[derive(Clone, Debug)]
+pub struct Conflict {
+    pub id: String,
+    pub base: String,
+    pub theirs: String,
+    pub mine: String,
+    pub merged: String,
+    pub clean: bool,
+    pub head: Option<Hash>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Update {
+    Saved,
+    Conflict(Conflict),
+}
 
 // Notes outer
 #[derive(Debug, Clone)]
@@ -91,10 +422,15 @@ pub struct Notes(Arc<Inner>);
 #[derive(Debug, Clone)]
 pub struct Inner {
     blobs: BlobsProtocol,
+    endpoint: Endpoint,
     _docs: Docs,
-    doc: Doc,
+    store: DocStore<Note>,
     ticket: DocTicket,
-    author: AuthorId,
+    trash_days: u64,
+    // None if the author secret isn't on this node
+    seal: Option<SealKey>,
+    // held from reading a note's text to writing it back
+    edits: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl Notes {
@@ -104,6 +440,8 @@ impl Notes {
         author: AuthorId,
         blobs: BlobsProtocol,
         docs: Docs,
+        endpoint: Endpoint,
+        trash_days: u64,
     ) -> Result<Self> {
         let author = author;
         let doc = match ticket {
@@ -118,11 +456,14 @@ impl Notes {
             .await?;
 
         Ok(Self(Arc::new(Inner {
+            store: DocStore::new(doc, author, blobs.clone()),
             blobs,
+            endpoint,
+            seal: seal_key(&docs, author).await,
             _docs: docs.clone(),
-            doc,
             ticket,
-            author,
+            trash_days,
+            edits: Arc::default(),
         })))
     }
 
@@ -131,6 +472,8 @@ impl Notes {
         author: AuthorId,
         blobs: BlobsProtocol,
         docs: Docs,
+        endpoint: Endpoint,
+        trash_days: u64,
     ) -> Result<Self> {
         let doc = docs.open(id.into()).await?;
         let doc = match doc {
@@ -140,104 +483,366 @@ impl Notes {
         let ticket = doc
             .share(ShareMode::Write, AddrInfoOptions::RelayAndAddresses)
             .await?;
-        let author = author;
         Ok(Self(Arc::new(Inner {
+            store: DocStore::new(doc, author, blobs.clone()),
             blobs,
+            endpoint,
+            seal: seal_key(&docs, author).await,
             _docs: docs.clone(),
-            doc,
             ticket,
-            author,
+            trash_days,
+            edits: Arc::default(),
         })))
     }
 
     pub fn id(&self) -> [u8; 32] {
-        self.0.doc.id().to_bytes()
+        self.0.store.doc().id().to_bytes()
     }
 
     pub fn ticket(&self) -> String {
         self.0.ticket.to_string()
     }
 
+    pub fn trash_days(&self) -> u64 {
+        self.0.trash_days
+    }
+
     #[allow(dead_code)]
     pub async fn leave(&self) -> Result<()> {
-        self.0.doc.leave().await?;
+        self.0.store.doc().leave().await?;
         Ok(())
     }
 
     #[allow(dead_code)]
     pub async fn share(&self) -> Result<()> {
-        self.0.doc.start_sync(vec![]).await?;
+        self.0.store.doc().start_sync(vec![]).await?;
         Ok(())
     }
 
-    pub async fn doc_subscribe(&self) -> Result<impl Stream<Item = Result<LiveEvent>> + use<>> {
-        self.0.doc.subscribe().await
-    }
-
     pub async fn run(&self) -> Result<()> {
-        let events = self.doc_subscribe().await?;
+        let events = self.0.store.subscribe().await?;
         task::spawn(info_loop(events));
+        let events = self.0.store.subscribe().await?;
+        task::spawn(attachment_loop(self.clone(), events));
+        task::spawn(purge_loop(self.clone()));
         Ok(())
     }
 
-    pub async fn create(&self, id: String, text: String) -> Result<()> {
+    // New note with a generated id , hands back the id
+    pub async fn create_titled(&self, title: String, text: String) -> Result<String> {
+        self.create_in(String::new(), title, text).await
+    }
+
+    // Same again inside a folder
+    pub async fn create_in(&self, folder: String, title: String, text: String) -> Result<String> {
+        let id = Uuid::now_v7().simple().to_string();
+        self.insert(id.clone(), folder, title, text).await?;
+        Ok(id)
+    }
+
+    pub async fn create(&self, id: String, title: String, text: String) -> Result<()> {
+        self.insert(id, String::new(), title, text).await
+    }
+
+    async fn insert(&self, id: String, folder: String, title: String, text: String) -> Result<()> {
         if text.len() > MAX_TEXT_LEN {
             bail!("text is too long, max size is {MAX_TEXT_LEN}");
         };
         let created = Utc::now().timestamp();
+        let meta = FrontMatter::parse(&text).0;
         let note = Note {
             id: id.clone(),
+            title,
+            old_titles: vec![],
             text,
             created,
             updated: created,
             is_delete: false,
+            deleted_at: 0,
+            deleted_by: None,
+            is_purge: false,
+            purged_at: 0,
+            attachments: vec![],
+            body: None,
+            meta,
+            folder,
+            private: false,
+            sealed: None,
+            locked: false,
+            pending: false,
         };
-        self.insert_bytes(id.as_bytes(), note.as_bytes()?).await
+        self.put_note(&id, note).await
     }
 
     pub async fn get_notes(&self) -> Result<Vec<Note>> {
-        let entries = self.0.doc.get_many(Query::single_latest_per_key()).await?;
         let mut notes = Vec::new();
-        // TODO remove once entries are unpin !
-        tokio::pin!(entries);
-        while let Some(entry) = entries.next().await {
-            let entry = entry?;
-            let note = self.note_from_entry(&entry).await?;
+        for record in self.0.store.list("").await? {
+            let note = self.note_from_record(record).await?;
             if !note.is_delete {
                 notes.push(note)
             }
-            // notes.push(note);
         }
-        // println!("{:#?}",notes);
         notes.sort_by_key(|n| Reverse(n.updated));
         Ok(notes)
     }
 
-    // Just get a vec
-    pub async fn get_note_vec(&self) -> Vec<String> {
-        let note_list_res = self.get_notes().await;
-        let items = match note_list_res {
-            Ok(notes) => notes.iter().map(|n| n.id.clone()).collect(),
-            Err(e) => vec![format!("{e}")],
+    // Just get a vec , a failure just leaves the side menu empty
+    pub async fn get_note_vec(&self) -> Vec<NoteLink> {
+        match self.get_notes().await {
+            Ok(notes) => notes.iter().map(|n| n.link()).collect(),
+            Err(e) => {
+                warn!("note list failed {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    // Lower cased titles and aliases to ids, for wiki links.
+    // Notes in a folder also answer to folder/title.
+    pub async fn title_index(&self) -> Result<HashMap<String, String>> {
+        let mut index: HashMap<String, String> = HashMap::new();
+        let notes = self.get_notes().await?;
+        for note in notes.iter() {
+            if note.locked {
+                continue;
+            }
+            for alias in note.meta.aliases.iter() {
+                index.insert(alias.to_lowercase(), note.id.clone());
+            }
+            index.insert(note.id.to_lowercase(), note.id.clone());
+            index.insert(note.title().to_lowercase(), note.id.clone());
+            index.insert(note.path().to_lowercase(), note.id.clone());
+        }
+        // old titles lose to anything a note is called now
+        for note in notes.iter().filter(|n| !n.locked) {
+            for old in note.old_titles.iter() {
+                index
+                    .entry(old.to_lowercase())
+                    .or_insert_with(|| note.id.clone());
+            }
+        }
+        Ok(index)
+    }
+
+    // Change the title , optionally fix up [[Old Title]] links in every note.
+    // Without relinking the old title stays on as an alias.
+    // Hands back the number of notes that had links rewritten.
+    pub async fn rename(&self, id: String, title: String, rewrite_links: bool) -> Result<usize> {
+        let Some(mut note) = self.find_note(id.clone()).await? else {
+            bail!("no note {id}");
         };
-        items
+        if note.locked {
+            bail!("{id} is someone else's private note");
+        }
+        let old_title = note.title().to_string();
+        if old_title == title {
+            return Ok(0);
+        }
+        note.title = title.clone();
+        note.old_titles.retain(|t| !t.eq_ignore_ascii_case(&title));
+        // a private title would leak through the alias
+        if !rewrite_links && !note.private {
+            note.old_titles.push(old_title.clone());
+        }
+        note.updated = Utc::now().timestamp();
+        self.put_note(&id, note).await?;
+        if !rewrite_links {
+            return Ok(0);
+        }
+        let mut count = 0;
+        for other in self.get_notes().await? {
+            if other.locked {
+                continue;
+            }
+            // reread with the head so an edit landing meanwhile isn't overwritten
+            let (other, head) = self.get_note_head(other.id).await?;
+            let Some(text) = rewrite_wiki_links(&other.text, &old_title, &title) else {
+                continue;
+            };
+            match self.update_checked(other.id.clone(), text, head).await? {
+                Update::Saved => count += 1,
+                Update::Conflict(_) => warn!("{} changed while relinking , left as is", other.id),
+            }
+        }
+        Ok(count)
+    }
+
+    // Every - [ ] item in every note , lines are within the note body
+    pub async fn tasks(&self) -> Result<Vec<(NoteLink, Task)>> {
+        let mut tasks = Vec::new();
+        for note in self.get_notes().await? {
+            let link = note.link();
+            for task in markdown::tasks(note.body()) {
+                tasks.push((link.clone(), task));
+            }
+        }
+        Ok(tasks)
+    }
+
+    // Check or uncheck a task , checked is the state the page showed
+    pub async fn toggle_task(&self, id: String, line: usize, checked: bool) -> Result<()> {
+        let note = self.get_note(id.clone()).await?;
+        let body = note.body();
+        let front = &note.text[..note.text.len() - body.len()];
+        let Some(body) = markdown::toggle_task(body, line, checked) else {
+            bail!("no task on line {line} of {id}");
+        };
+        self.update_note(id, format!("{front}{body}")).await
+    }
+
+    // Front matter only, no markdown rendering
+    pub async fn get_meta(&self, id: String) -> Result<FrontMatter> {
+        Ok(self.get_note(id).await?.meta)
+    }
+
+    pub async fn notes_with_tag(&self, tag: &str) -> Result<Vec<Note>> {
+        let notes = self.get_notes().await?;
+        Ok(notes.into_iter().filter(|n| n.meta.has_tag(tag)).collect())
+    }
+
+    // Every tag and how many notes carry it
+    pub async fn tag_cloud(&self) -> Result<Vec<(String, usize)>> {
+        let mut tags: BTreeMap<String, usize> = BTreeMap::new();
+        for note in self.get_notes().await? {
+            for tag in note.meta.tags {
+                *tags.entry(tag).or_default() += 1;
+            }
+        }
+        Ok(tags.into_iter().collect())
+    }
+
+    // None for ids that aren't in the doc , get_note makes up an empty one
+    pub async fn find_note(&self, id: String) -> Result<Option<Note>> {
+        match self.0.store.get(&id).await? {
+            Some(record) => Ok(Some(self.note_from_record(record).await?)),
+            None => Ok(None),
+        }
     }
 
     pub async fn get_note(&self, id: String) -> Result<Note> {
-        let mut ex_key = id.as_bytes().to_vec();
-        ex_key.push(0);
-        let entry_option = self
+        let (note, _) = self.get_note_head(id).await?;
+        Ok(note)
+    }
+
+    // Note and the content hash it was loaded from
+    pub async fn get_note_head(&self, id: String) -> Result<(Note, Option<Hash>)> {
+        match self.0.store.get(&id).await? {
+            Some(record) => {
+                let hash = record.hash;
+                Ok((self.note_from_record(record).await?, Some(hash)))
+            }
+            None => Ok((Note::missing_note(id), None)),
+        }
+    }
+
+    // For the web page , pulls in whatever the download policy left behind
+    pub async fn open_note(&self, id: String) -> Result<Note> {
+        self.want(&id).await?;
+        let mut note = self.get_note(id.clone()).await?;
+        if note.pending {
+            if let Some(record) = self.0.store.get(&id).await? {
+                if let Err(e) = self.fetch(record.hash).await {
+                    warn!("fetch of {} failed {:?}", id, e);
+                }
+            }
+            note = self.get_note(id.clone()).await?;
+        }
+        // the entry came down but the body blob didn't
+        if let (Some(hash), true) = (note.body, note.pending) {
+            if let Err(e) = self.fetch(hash).await {
+                warn!("body fetch of {} failed {:?}", id, e);
+            }
+            note = self.get_note(id).await?;
+        }
+        for attachment in note.attachments.iter_mut() {
+            attachment.pending = !self.is_local(attachment.hash).await;
+        }
+        Ok(note)
+    }
+
+    pub async fn download(&self) -> Result<Download> {
+        Ok(Download::from_policy(&self.0.store.download_policy().await?))
+    }
+
+    pub async fn set_download(&self, download: Download) -> Result<()> {
+        self.0.store.set_download_policy(download.policy()).await
+    }
+
+    // On demand notebooks keep following the notes that have been opened
+    async fn want(&self, id: &str) -> Result<()> {
+        let DownloadPolicy::NothingExcept(mut filters) = self.0.store.download_policy().await?
+        else {
+            return Ok(());
+        };
+        let wanted = [
+            doc_store::exact_filter(id),
+            doc_store::exact_filter(&body_key(id)),
+            doc_store::prefix_filter(&attachment_prefix(id)),
+        ];
+        if wanted.iter().all(|w| filters.contains(w)) {
+            return Ok(());
+        }
+        for filter in wanted {
+            if !filters.contains(&filter) {
+                filters.push(filter);
+            }
+        }
+        self.0
+            .store
+            .set_download_policy(DownloadPolicy::NothingExcept(filters))
+            .await
+    }
+
+    // Ask the doc's sync peers for a blob , the first that has it wins
+    async fn fetch(&self, hash: Hash) -> Result<()> {
+        if self.is_local(hash).await {
+            return Ok(());
+        }
+        let peers = self.0.store.doc().get_sync_peers().await?.unwrap_or_default();
+        for peer in peers {
+            let Ok(node) = EndpointId::from_bytes(&peer) else {
+                continue;
+            };
+            let attempt = async {
+                let conn = self.0.endpoint.connect(node, iroh_blobs::ALPN).await?;
+                self.0
+                    .blobs
+                    .store()
+                    .remote()
+                    .fetch(conn, HashAndFormat::raw(hash))
+                    .await?;
+                anyhow::Ok(())
+            };
+            match tokio::time::timeout(FETCH_TIMEOUT, attempt).await {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(e)) => warn!("{} could not send {} {:?}", node, hash, e),
+                Err(_) => warn!("{} timed out sending {}", node, hash),
+            }
+        }
+        bail!("no peer could send {hash}")
+    }
+
+    async fn is_local(&self, hash: Hash) -> bool {
+        match self
             .0
-            .doc
-            .get_one(Query::single_latest_per_key().key_exact(&ex_key))
-            .await?;
-        match entry_option {
-            Some(entry) => self.note_from_entry(&entry).await,
-            None => Ok(Note::missing_note(id.clone())),
+            .blobs
+            .store()
+            .remote()
+            .local(HashAndFormat::raw(hash))
+            .await
+        {
+            Ok(local) => local.is_complete(),
+            Err(_) => false,
         }
     }
 
     pub async fn update_note(&self, id: String, text: String) -> Result<()> {
+        let _edit = self.0.edits.lock().await;
+        self.write_text(id, text).await
+    }
+
+    // Callers hold Inner.edits
+    async fn write_text(&self, id: String, text: String) -> Result<()> {
         if text.len() > MAX_TEXT_LEN {
             bail!("text is too long, max size is {MAX_TEXT_LEN}");
         };
@@ -246,130 +851,676 @@ impl Notes {
             Ok(note) => note,
             Err(_) => Note::missing_note("missing".to_string()),
         };
+        if note.locked {
+            bail!("{id} is someone else's private note");
+        }
+        note.meta = FrontMatter::parse(&text).0;
         note.text = text;
         note.updated = Utc::now().timestamp();
-        let res = self.update_bytes(id.as_bytes(), note).await;
-        match res {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
+        self.put_note(&id, note).await
+    }
+
+    // Only write if nobody else has written since `base` was read.
+    pub async fn update_checked(
+        &self,
+        id: String,
+        text: String,
+        base: Option<Hash>,
+    ) -> Result<Update> {
+        if text.len() > MAX_TEXT_LEN {
+            bail!("text is too long, max size is {MAX_TEXT_LEN}");
+        };
+        let edit = self.0.edits.lock().await;
+        let (current, head) = self.get_note_head(id.clone()).await?;
+        if head == base {
+            self.write_text(id, text).await?;
+            return Ok(Update::Saved);
         }
+        drop(edit);
+        // Old content may have gone, merge against nothing.
+        let base_text = match base {
+            Some(hash) => match self.0.store.load(hash).await {
+                Ok(note) => self.load_body(note).await.map(|n| n.text).unwrap_or_default(),
+                Err(_) => String::new(),
+            },
+            None => String::new(),
+        };
+        warn!("stale edit on {} , {:?} is now {:?}", id, base, head);
+        let (merged, clean) = merge_texts(&base_text, &text, &current.text);
+        Ok(Update::Conflict(Conflict {
+            id,
+            base: base_text,
+            theirs: current.text,
+            mine: text,
+            merged,
+            clean,
+            head,
+        }))
     }
 
-    #[allow(dead_code)]
-    pub async fn delete_hidden(&self) -> Result<()> {
-        let entries = self.0.doc.get_many(Query::single_latest_per_key()).await?;
-        tokio::pin!(entries);
-        while let Some(entry) = entries.next().await {
-            let entry = entry?;
-            let note = self.note_from_entry(&entry).await?;
-            if note.is_delete {
-                println!("deleting => {:#?}", note);
-                let val = self
-                    .0
-                    .doc
-                    .del(self.0.author, entry.key().to_owned())
-                    .await?;
-                println!("{:?} nodes deleted", val);
+    // Add a file to the blob store and hang it off the note
+    pub async fn attach(&self, id: String, name: String, data: Bytes) -> Result<Attachment> {
+        let Some(mut note) = self.find_note(id.clone()).await? else {
+            bail!("no note {id}");
+        };
+        if note.locked {
+            bail!("{id} is someone else's private note");
+        }
+        // the blob would sync in the clear
+        if note.private {
+            bail!("{id} is private , attachments can't be sealed");
+        }
+        let size = data.len() as u64;
+        let hash = self.0.blobs.add_bytes(data).await?.hash;
+        let attachment = Attachment {
+            name,
+            hash,
+            size,
+            pending: false,
+        };
+        self.link_attachment(&id, &attachment).await?;
+        note.attachments.retain(|a| a.hash != hash);
+        note.attachments.push(attachment.clone());
+        note.updated = Utc::now().timestamp();
+        self.put_note(&id, note).await?;
+        Ok(attachment)
+    }
+
+    // Only hand back blobs that are actually attached to the note
+    pub async fn get_attachment(&self, id: String, hash: Hash) -> Result<Option<(Attachment, Bytes)>> {
+        let note = self.get_note(id).await?;
+        match note.attachments.into_iter().find(|a| a.hash == hash) {
+            Some(attachment) => {
+                // not downloaded under the notebook's policy
+                if let Err(e) = self.fetch(hash).await {
+                    warn!("attachment fetch failed {:?}", e);
+                }
+                let data = self.0.blobs.get_bytes(hash).await?;
+                Ok(Some((attachment, data)))
             }
+            None => Ok(None),
         }
+    }
+
+    // The doc entry points at the blob so the docs downloader fetches it
+    async fn link_attachment(&self, id: &str, attachment: &Attachment) -> Result<()> {
+        let key = attachment_key(id, &attachment.hash);
+        let side = Side::new(&key, String::new(), Some(attachment.hash));
+        self.put_side(&key, &side).await?;
         Ok(())
     }
 
-    pub async fn set_delete(&self, id: String) -> Result<()> {
+    // Side entry content goes in as a blob of its own , hands back its hash
+    async fn put_side(&self, key: &str, side: &Side) -> Result<Hash> {
+        let data = serde_json::to_vec(side)?;
+        let size = data.len() as u64;
+        let hash = self.0.blobs.add_bytes(data).await?.hash;
+        self.0.store.put_hash(key, hash, size).await?;
+        Ok(hash)
+    }
+
+    // Dropped back under INLINE_TEXT_LEN , the old body entry goes
+    async fn drop_body(&self, id: &str, note: &mut Note) -> Result<()> {
+        if note.body.take().is_some() {
+            self.0.store.delete(&body_key(id)).await?;
+        }
+        Ok(())
+    }
+
+    // Wait for an attachment side entry from a peer , then the file it
+    // points at. Entries the download policy leaves out never arrive.
+    async fn fetch_attachment(&self, side: Hash) -> Result<()> {
+        let waited = tokio::time::timeout(FETCH_TIMEOUT, async {
+            while !self.is_local(side).await {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        })
+        .await;
+        if waited.is_err() {
+            return Ok(());
+        }
+        let side = Side::decode(&self.0.blobs.get_bytes(side).await?)?;
+        match side.hash {
+            Some(hash) => self.fetch(hash).await,
+            None => bail!("attachment entry {} has no hash", side.id),
+        }
+    }
+
+    // Deleted notes , newest first
+    pub async fn get_trash(&self) -> Result<Vec<Note>> {
+        let mut notes = Vec::new();
+        for record in self.0.store.list("").await? {
+            let note = self.note_from_record(record).await?;
+            if note.is_delete {
+                notes.push(note)
+            }
+        }
+        notes.sort_by_key(|n| Reverse(n.deleted_at));
+        Ok(notes)
+    }
+
+    // Really delete purged notes that have sat in the trash for long enough.
+    pub async fn purge_expired(&self) -> Result<usize> {
+        let now = Utc::now().timestamp();
+        let mut count = 0;
+        for note in self.get_trash().await? {
+            if !note.is_purge || note.purge_after(self.0.trash_days) > now {
+                continue;
+            }
+            warn!("purging => {} {}", note.id, note.title());
+            self.0.store.delete(&note.id).await?;
+            // prefix delete takes all the attachments with it
+            self.0
+                .store
+                .delete_prefix(&attachment_prefix(&note.id))
+                .await?;
+            self.0.store.delete(&body_key(&note.id)).await?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // by is the web user that pressed delete
+    pub async fn set_delete(&self, id: String, by: &str) -> Result<()> {
+        let mut note = self.get_note(id.clone()).await?;
+        note.is_delete = true;
+        note.is_purge = false;
+        note.purged_at = 0;
+        note.deleted_at = Utc::now().timestamp();
+        note.deleted_by = Some(by.to_string());
+        self.put_note(&id, note).await
+    }
+
+    pub async fn restore(&self, id: String) -> Result<()> {
         let mut note = self.get_note(id.clone()).await?;
-        note.is_delete = !note.is_delete;
-        self.update_bytes(id, note).await
+        note.is_delete = false;
+        note.is_purge = false;
+        note.purged_at = 0;
+        note.deleted_at = 0;
+        note.deleted_by = None;
+        note.updated = Utc::now().timestamp();
+        self.put_note(&id, note).await
+    }
+
+    // Seal or unseal a note , only the author can do either
+    pub async fn set_private(&self, id: String, private: bool) -> Result<()> {
+        let mut note = self.get_note(id.clone()).await?;
+        if note.locked {
+            bail!("{id} is someone else's private note");
+        }
+        if private && !note.private && !note.attachments.is_empty() {
+            bail!("{id} has attachments , they can't be sealed");
+        }
+        note.private = private;
+        // old titles sit outside the seal
+        if private {
+            note.old_titles.clear();
+        }
+        self.put_note(&id, note).await
+    }
+
+    // Mark for removal , the purge loop does the actual delete
+    pub async fn purge(&self, id: String) -> Result<()> {
+        let mut note = self.get_note(id.clone()).await?;
+        if !note.is_delete {
+            bail!("only deleted notes can be purged");
+        }
+        note.is_purge = true;
+        note.purged_at = Utc::now().timestamp();
+        self.put_note(&id, note).await
     }
 
     // Doc data manipulation
 
-    // TODO null byte the id for weird reasons
-    async fn insert_bytes(&self, key: impl AsRef<[u8]>, value: Bytes) -> Result<()> {
-        // null byte exend the key
-        let mut ex_key = key.as_ref().to_vec();
-        // add the null byte, why ??
-        ex_key.push(0);
-        // harrahs b5 to not do this
-        self.0.doc.set_bytes(self.0.author, ex_key, value).await?;
+    async fn put_note(&self, id: &str, mut note: Note) -> Result<()> {
+        // writing a placeholder would wipe the real content for everyone
+        if note.pending {
+            bail!("{id} has not been downloaded yet");
+        }
+        // someone else's private note , write the sealed part back untouched
+        if note.locked {
+            self.0.store.put(id, &note).await?;
+            return Ok(());
+        }
+        if note.private {
+            return self.put_sealed(id, note).await;
+        }
+        note.sealed = None;
+        if note.text.len() > INLINE_TEXT_LEN {
+            // same trick as attachments so the body gets downloaded
+            let side = Side::new(&body_key(id), note.text.clone(), None);
+            note.body = Some(self.put_side(&body_key(id), &side).await?);
+            note.text = BODY_PLACEHOLDER.to_string();
+        } else {
+            self.drop_body(id, &mut note).await?;
+        }
+        self.0.store.put(id, &note).await?;
         Ok(())
     }
 
-    async fn update_bytes(&self, key: impl AsRef<[u8]>, note: Note) -> Result<()> {
-        let content = note.as_bytes()?;
-        self.insert_bytes(key, content).await
+    // Title , text and the attachment list go into the seal , big ones into
+    // a body blob the same way as plain notes.
+    async fn put_sealed(&self, id: &str, mut note: Note) -> Result<()> {
+        let Some(key) = &self.0.seal else {
+            bail!("no author key on this node for private notes");
+        };
+        let plain = serde_json::to_vec(&PrivateBody {
+            title: note.title.clone(),
+            text: note.text.clone(),
+            attachments: std::mem::take(&mut note.attachments),
+        })?;
+        let (nonce, data) = key.seal(id, &plain)?;
+        let mut sealed = Sealed {
+            author: self.0.store.author().to_string(),
+            nonce,
+            data: String::new(),
+        };
+        if data.len() > INLINE_TEXT_LEN {
+            let side = Side::new(&body_key(id), BASE64.encode(&data), None);
+            note.body = Some(self.put_side(&body_key(id), &side).await?);
+        } else {
+            sealed.data = BASE64.encode(&data);
+            self.drop_body(id, &mut note).await?;
+        }
+        note.title = String::new();
+        note.text = PRIVATE_TEXT.to_string();
+        note.meta = FrontMatter::default();
+        note.sealed = Some(sealed);
+        self.0.store.put(id, &note).await?;
+        Ok(())
     }
 
-    async fn note_from_entry(&self, entry: &Entry) -> Result<Note> {
-        let mut id = String::from_utf8(entry.key().to_owned()).context("invalid key")?;
-        match self.0.blobs.get_bytes(entry.content_hash()).await {
-            Ok(b) => Note::from_bytes(b),
-            Err(e) => {
-                id.pop();
-                println!("{:#?} this is probably it {:#?}", &id, e);
-                return Ok(Note::missing_note(id));
+    // Open a private note , or lock it if it isn't ours
+    async fn unseal(&self, mut note: Note, sealed: Sealed) -> Result<Note> {
+        let data = match note.body {
+            Some(hash) => match self.0.blobs.get_bytes(hash).await {
+                Ok(bytes) => match Side::decode(&bytes)
+                    .and_then(|side| Ok(BASE64.decode(side.text.as_bytes())?))
+                {
+                    Ok(data) => Some(data),
+                    Err(e) => {
+                        warn!("sealed body {} for {} is broken {:?}", hash, note.id, e);
+                        None
+                    }
+                },
+                Err(_) => None,
+            },
+            None => sealed.data().ok(),
+        };
+        if data.is_none() {
+            note.pending = true;
+            note.sealed = Some(sealed);
+            return Ok(note);
+        }
+        let ours = sealed.author == self.0.store.author().to_string();
+        let opened = match (&self.0.seal, data) {
+            (Some(key), Some(data)) if ours => key.open(&note.id, &sealed.nonce, &data).ok(),
+            _ => None,
+        };
+        match opened.and_then(|plain| serde_json::from_slice::<PrivateBody>(&plain).ok()) {
+            Some(body) => {
+                note.title = body.title;
+                note.meta = FrontMatter::parse(&body.text).0;
+                note.text = body.text;
+                note.attachments = body.attachments;
+                note.private = true;
+            }
+            None => {
+                note.locked = true;
+                note.sealed = Some(sealed);
             }
         }
+        Ok(note)
+    }
+
+    // Pull in the body blob if it has one
+    async fn load_body(&self, mut note: Note) -> Result<Note> {
+        if let Some(sealed) = note.sealed.take() {
+            return self.unseal(note, sealed).await;
+        }
+        // Older nodes drop the body field when they write the note back ,
+        // the .body entry is still there so their edit of the placeholder
+        // doesn't get to replace the real text
+        if note.body.is_none() && note.text.contains(BODY_PLACEHOLDER) {
+            note.body = self.0.store.get_hash(&body_key(&note.id)).await?;
+        }
+        if let Some(hash) = note.body {
+            note.text = match self.0.blobs.get_bytes(hash).await {
+                // pending too , so nobody writes the broken text back
+                Ok(body) => match Side::decode(&body) {
+                    Ok(side) => side.text,
+                    Err(e) => {
+                        warn!("body {} for {} is broken {:?}", hash, note.id, e);
+                        note.pending = true;
+                        BODY_BROKEN.to_string()
+                    }
+                },
+                Err(e) => {
+                    warn!("body {} for {} is missing {:?}", hash, note.id, e);
+                    note.pending = true;
+                    BODY_MISSING.to_string()
+                }
+            };
+            if note.meta.is_empty() {
+                note.meta = FrontMatter::parse(&note.text).0;
+            }
+        }
+        Ok(note)
+    }
+
+    async fn note_from_record(&self, record: Record<Note>) -> Result<Note> {
+        match record.value {
+            Some(note) => self.load_body(note).await,
+            None => {
+                warn!("{} has not been downloaded yet", record.key);
+                let mut note = Note::missing_note(record.key);
+                note.pending = true;
+                Ok(note)
+            }
+        }
+    }
+
+    // Snapshots are bounce down collections tagged notes-<timestamp> , newest first
+    pub async fn list_snapshots(&self) -> Result<Vec<Snapshot>> {
+        let mut tag_scan = self.0.blobs.tags().list_prefix(SNAPSHOT_PREFIX).await?;
+        let mut snapshots = Vec::new();
+        while let Some(tag) = tag_scan.next().await {
+            let tag = tag?;
+            let name = String::from_utf8(tag.name.0.to_vec()).context("invalid tag")?;
+            let created = name
+                .strip_prefix(SNAPSHOT_PREFIX)
+                .and_then(|ts| ts.parse::<i64>().ok())
+                .unwrap_or_default();
+            snapshots.push(Snapshot {
+                tag: name,
+                hash: tag.hash,
+                created,
+            });
+        }
+        snapshots.sort_by_key(|s| Reverse(s.created));
+        Ok(snapshots)
+    }
+
+    // The notes inside a snapshot , for the restore picker
+    pub async fn snapshot_notes(&self, tag: &str) -> Result<Vec<NoteLink>> {
+        let coll = self.load_snapshot(tag).await?;
+        let titles = self.snapshot_titles(&coll).await?;
+        let mut notes = Vec::new();
+        for (name, _) in coll.iter() {
+            if let Some(id) = note_file_id(name) {
+                notes.push(NoteLink {
+                    title: titles.get(&id).cloned().unwrap_or_else(|| id.clone()),
+                    id,
+                    locked: false,
+                });
+            }
+        }
+        notes.sort_by(|a, b| a.title.cmp(&b.title));
+        Ok(notes)
+    }
+
+    // Snapshot every so often , the first one goes straight away.
+    // A zero period would panic the interval , so it does nothing.
+    pub fn schedule_snapshots(&self, every: Duration) {
+        if every.is_zero() {
+            return;
+        }
+        task::spawn(snapshot_loop(self.clone(), every));
     }
 
     // Save out the docs as date stamped .md files
-    #[allow(dead_code)]
-    pub async fn bounce_down(&self) -> Result<()> {
-        let entries = self.0.doc.get_many(Query::single_latest_per_key()).await?;
+    // Nothing is written if the content matches the last snapshot,
+    // hands back the new tag otherwise.
+    pub async fn bounce_down(&self) -> Result<Option<String>> {
         let mut notes = Vec::new();
-        // TODO remove once entries are unpin !
-        tokio::pin!(entries);
-        while let Some(entry) = entries.next().await {
-            let entry = entry?;
-            let note = self.note_from_entry(&entry).await?;
-            if !note.is_delete {
+        // the .md files are named by id , keep the titles alongside
+        let mut titles: BTreeMap<String, String> = BTreeMap::new();
+        let now = Local::now();
+        let folder = now.format("notes/%Y/%m/%d/").to_string();
+        for record in self.0.store.list("").await? {
+            let note = self.note_from_record(record).await?;
+            // snapshots replicate , private and pending notes stay in the doc
+            if !note.is_delete && note.is_shareable() {
+                titles.insert(note.id.clone(), note.title().to_string());
                 let h = self.0.blobs.add_bytes(note.text).await?.hash;
-                let mut file_name = Local::now().format("notes/%Y/%m/%d/").to_string();
+                let mut file_name = folder.clone();
                 file_name.push_str(&note.id.clone());
+                // attachments sit in a folder next to the note
+                for attachment in note.attachments.iter() {
+                    notes.push((
+                        format!("{file_name}.files/{}", attachment.name),
+                        attachment.hash,
+                    ));
+                }
                 // add markdown file extension for good measure
                 file_name.push_str(".md");
                 notes.push((format!("{file_name}"), h));
             }
         }
+        let titles = serde_json::to_vec(&titles)?;
+        let h = self.0.blobs.add_bytes(titles).await?.hash;
+        notes.push((format!("{folder}{TITLES_NAME}"), h));
+
+        // Same content as last time ?
+        let current = fingerprint(&notes);
+        if let Some(last) = self.list_snapshots().await?.first() {
+            if let Ok(coll) = Collection::load(last.hash, self.0.blobs.store()).await {
+                let previous: Vec<(String, Hash)> = coll.iter().cloned().collect();
+                if fingerprint(&previous) == current {
+                    return Ok(None);
+                }
+            }
+        }
         // print!("{:#?}", notes);
         let col = notes.into_iter().collect::<Collection>();
         let col_hash = col.store(&self.0.blobs).await?;
         // tag it for replication
-        let dt = Local::now().timestamp();
-        self.0
-            .blobs
-            .tags()
-            .set(format!("notes-{}", dt), &col_hash)
-            .await?;
+        let tag = format!("{SNAPSHOT_PREFIX}{}", now.timestamp());
+        self.0.blobs.tags().set(tag.clone(), &col_hash).await?;
         // println!("notes bounce down {:?}", col_hash);
-        Ok(())
+        Ok(Some(tag))
     }
-    #[allow(dead_code)]
-    pub async fn bounce_up(&self, id: &str) -> Result<()> {
-        let tag = match self.0.blobs.tags().get(id).await? {
-            Some(tag) => tag,
-            None => return Err(anyhow!("no notes tag")),
+
+    // Rebuild notes from a snapshot , all of them or just the listed ids.
+    pub async fn bounce_up(&self, id: &str, only: Option<&[String]>) -> Result<usize> {
+        let coll = self.load_snapshot(id).await?;
+        let mut titles = self.snapshot_titles(&coll).await?;
+        let wanted = |note_id: &str| match only {
+            Some(ids) => ids.iter().any(|i| i == note_id),
+            None => true,
         };
-        let coll = Collection::load(tag.hash, self.0.blobs.store()).await?;
-        println!("{:#?}", coll);
+        let mut attachments = Vec::new();
+        let mut count = 0;
         for (name, hash) in coll.iter() {
+            // notes/YYYY/MM/DD/<id>.files/<name>
+            if let Some((note_path, file_name)) = name.split_once(".files/") {
+                attachments.push((note_path.to_string(), file_name.to_string(), *hash));
+                continue;
+            }
+            let Some(split_name) = note_file_id(name) else {
+                continue;
+            };
+            if !wanted(&split_name) {
+                continue;
+            }
             let data_bytes = self.0.blobs.get_bytes(hash.as_bytes()).await?;
             let text = String::from_utf8(data_bytes.to_vec())?;
-            let mut split_name = name
-                .split("/")
-                .collect::<Vec<&str>>()
-                .last()
-                .unwrap()
-                .to_string();
-            // remove the .md
-            split_name.truncate(split_name.len() - 3);
-            self.create(split_name, text).await?
+            let title = titles.remove(&split_name).unwrap_or_default();
+            self.create(split_name, title, text).await?;
+            count += 1;
+        }
+        // Reattach once all the notes exist
+        for (note_path, file_name, hash) in attachments {
+            let id = match note_path.rsplit_once("/") {
+                Some((_, id)) => id.to_string(),
+                None => note_path,
+            };
+            if !wanted(&id) {
+                continue;
+            }
+            let data = self.0.blobs.get_bytes(hash).await?;
+            self.attach(id, file_name, data).await?;
+        }
+        Ok(count)
+    }
+
+    async fn load_snapshot(&self, tag: &str) -> Result<Collection> {
+        let tag = match self.0.blobs.tags().get(tag).await? {
+            Some(tag) => tag,
+            None => return Err(anyhow!("no notes tag")),
+        };
+        let coll = Collection::load(tag.hash, self.0.blobs.store()).await?;
+        Ok(coll)
+    }
+
+    async fn snapshot_titles(&self, coll: &Collection) -> Result<BTreeMap<String, String>> {
+        match coll.iter().find(|(name, _)| name.ends_with(TITLES_NAME)) {
+            Some((_, hash)) => {
+                let data_bytes = self.0.blobs.get_bytes(*hash).await?;
+                Ok(serde_json::from_slice(&data_bytes).unwrap_or_default())
+            }
+            None => Ok(BTreeMap::new()),
         }
-        Ok(())
     }
     // End direct doc manipulation
 }
 
-async fn info_loop(events: impl Stream<Item = Result<LiveEvent>>) {
+// Swap the target of [[old]] , [[old#heading]] and [[old|label]] for the new
+// title , None if nothing changed. Titles match without case like title_index.
+fn rewrite_wiki_links(text: &str, old: &str, new: &str) -> Option<String> {
+    let old = old.to_lowercase();
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut changed = false;
+    while let Some(start) = rest.find("[[") {
+        let inner_start = start + 2;
+        let Some(len) = rest[inner_start..].find("]]") else {
+            break;
+        };
+        let inner = &rest[inner_start..inner_start + len];
+        out.push_str(&rest[..inner_start]);
+        let (target, suffix) = inner.split_at(inner.find(['#', '|']).unwrap_or(inner.len()));
+        if markdown::wiki_target(target).to_lowercase() == old {
+            out.push_str(new);
+            out.push_str(suffix);
+            changed = true;
+        } else {
+            out.push_str(inner);
+        }
+        out.push_str("]]");
+        rest = &rest[inner_start + len + 2..];
+    }
+    out.push_str(rest);
+    changed.then_some(out)
+}
+
+// Three way merge , false when it left conflict markers in
+fn merge_texts(base: &str, mine: &str, theirs: &str) -> (String, bool) {
+    match diffy::merge(base, mine, theirs) {
+        Ok(merged) => (merged, true),
+        Err(merged) => (merged, false),
+    }
+}
+
+fn attachment_prefix(id: &str) -> String {
+    format!("{ATTACHMENT_PREFIX}{id}/")
+}
+
+fn attachment_key(id: &str, hash: &Hash) -> String {
+    format!("{}{}", attachment_prefix(id), hash.to_hex())
+}
+
+async fn seal_key(docs: &Docs, author: AuthorId) -> Option<SealKey> {
+    match docs.author_export(author).await {
+        Ok(Some(author)) => Some(SealKey::derive(&author.to_bytes())),
+        Ok(None) => None,
+        Err(e) => {
+            warn!("no author secret for private notes {:?}", e);
+            None
+        }
+    }
+}
+
+fn body_key(id: &str) -> String {
+    format!("{BODY_PREFIX}{id}")
+}
+
+// notes/YYYY/MM/DD/<id>.md -> <id>
+fn note_file_id(name: &str) -> Option<String> {
+    let file_name = name.rsplit('/').next()?;
+    file_name.strip_suffix(".md").map(|id| id.to_string())
+}
+
+// Snapshot content without the date folder, so the same notes
+// on a different day come out the same.
+fn fingerprint(entries: &[(String, Hash)]) -> Hash {
+    let mut items: Vec<(&str, &[u8; 32])> = entries
+        .iter()
+        .map(|(name, hash)| (name.splitn(5, '/').nth(4).unwrap_or(name), hash.as_bytes()))
+        .collect();
+    items.sort();
+    let mut buf = Vec::new();
+    for (name, hash) in items {
+        buf.extend_from_slice(name.as_bytes());
+        buf.push(0);
+        buf.extend_from_slice(hash);
+    }
+    Hash::new(buf)
+}
+
+fn trash_secs(days: u64) -> i64 {
+    (days * 24 * 60 * 60) as i64
+}
+
+fn format_time(timestamp: i64) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(dt) => dt.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string(),
+        None => String::from("-"),
+    }
+}
+
+async fn snapshot_loop(notes: Notes, every: Duration) {
+    let mut ticker = tokio::time::interval(every);
+    loop {
+        ticker.tick().await;
+        match notes.bounce_down().await {
+            Ok(Some(tag)) => warn!("notes snapshot {}", tag),
+            Ok(None) => warn!("notes unchanged , no snapshot"),
+            Err(e) => error!("snapshot failed {:#?}", e),
+        }
+    }
+}
+
+// Check the trash once an hour
+async fn purge_loop(notes: Notes) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(3600));
+    loop {
+        ticker.tick().await;
+        match notes.purge_expired().await {
+            Ok(0) => {}
+            Ok(count) => warn!("{} notes purged", count),
+            Err(e) => error!("purge failed {:#?}", e),
+        }
+    }
+}
+
+// Attachment entries from peers , fetch the files they point at
+async fn attachment_loop(notes: Notes, events: impl Stream<Item = Result<Change>>) {
+    tokio::pin!(events);
+    while let Some(Ok(event)) = events.next().await {
+        if !event.remote || event.deleted || !event.key.starts_with(ATTACHMENT_PREFIX) {
+            continue;
+        }
+        let notes = notes.clone();
+        task::spawn(async move {
+            if let Err(e) = notes.fetch_attachment(event.hash).await {
+                debug!("attachment {} not fetched {:#}", event.key, e);
+            }
+        });
+    }
+}
+
+async fn info_loop(events: impl Stream<Item = Result<Change>>) {
     warn!("Start info loop");
     tokio::pin!(events);
     while let Some(event) = events.next().await {
@@ -380,7 +1531,127 @@ async fn info_loop(events: impl Stream<Item = Result<LiveEvent>>) {
                 break;
             }
         };
-        warn!("{:#?}", event);
+        let source = if event.remote { "remote" } else { "local" };
+        match event.deleted {
+            true => warn!("{} delete {}", source, event.key),
+            false => warn!("{} write {} => {}", source, event.key, event.hash),
+        }
     }
     warn!("escape!!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged_text_merges_to_mine() {
+        let base = "one\ntwo\nthree\n";
+        let mine = "one\n2\nthree\n";
+        assert_eq!(merge_texts(base, mine, base), (mine.to_string(), true));
+    }
+
+    #[test]
+    fn separate_edits_merge_clean() {
+        let base = "one\ntwo\nthree\nfour\nfive\n";
+        let mine = "ONE\ntwo\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\nFIVE\n";
+        let (merged, clean) = merge_texts(base, mine, theirs);
+        assert!(clean);
+        assert_eq!(merged, "ONE\ntwo\nthree\nfour\nFIVE\n");
+    }
+
+    #[test]
+    fn same_line_conflicts() {
+        let base = "one\ntwo\nthree\n";
+        let (merged, clean) = merge_texts(base, "one\nmine\nthree\n", "one\ntheirs\nthree\n");
+        assert!(!clean);
+        assert!(merged.contains("mine") && merged.contains("theirs"));
+        assert!(merged.contains("<<<<<<<"));
+    }
+
+    #[test]
+    fn retention_counts_from_the_purge() {
+        let day = trash_secs(1);
+        let mut note = Note::missing_note("note-1".to_string());
+        note.deleted_at = 1_000_000;
+        assert_eq!(note.purge_after(7), 1_000_000 + 7 * day);
+        note.purged_at = 1_000_000 + 30 * day;
+        assert_eq!(note.purge_after(7), 1_000_000 + 37 * day);
+    }
+
+    #[test]
+    fn only_readable_notes_are_shared() {
+        let note = Note::missing_note("note-1".to_string());
+        assert!(note.is_shareable());
+        let pending = Note {
+            pending: true,
+            ..note.clone()
+        };
+        assert!(!pending.is_shareable());
+        let private = Note {
+            private: true,
+            ..note.clone()
+        };
+        assert!(!private.is_shareable());
+        let locked = Note {
+            locked: true,
+            ..note
+        };
+        assert!(!locked.is_shareable());
+    }
+
+    #[test]
+    fn attachment_keys_sit_under_the_note() {
+        let hash = Hash::new(b"cat picture");
+        let key = attachment_key("note-1", &hash);
+        assert_eq!(key, format!(".attachment/note-1/{}", hash.to_hex()));
+        assert!(key.starts_with(&attachment_prefix("note-1")));
+        assert!(!key.starts_with(&attachment_prefix("note")));
+    }
+
+    // the note layout from before side entries
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct OldNote {
+        id: String,
+        text: String,
+        created: i64,
+        updated: i64,
+        is_delete: bool,
+    }
+
+    #[test]
+    fn side_entries_look_deleted_to_old_nodes() {
+        let hash = Hash::new(b"file");
+        for side in [
+            Side::new(&body_key("n1"), "big text".to_string(), None),
+            Side::new(&attachment_key("n1", &hash), String::new(), Some(hash)),
+        ] {
+            let bytes = serde_json::to_vec(&side).unwrap();
+            let old: OldNote = serde_json::from_slice(&bytes).unwrap();
+            assert!(old.is_delete);
+            let back = Side::decode(&bytes).unwrap();
+            assert_eq!(back.text, side.text);
+            assert_eq!(back.hash, side.hash);
+        }
+    }
+
+    #[test]
+    fn wiki_links_follow_a_rename() {
+        let text = "see [[Old]] , [[old#Heading]] and [[OLD|the old one]]";
+        assert_eq!(
+            rewrite_wiki_links(text, "Old", "New").as_deref(),
+            Some("see [[New]] , [[New#Heading]] and [[New|the old one]]")
+        );
+    }
+
+    #[test]
+    fn other_wiki_links_stay() {
+        assert_eq!(rewrite_wiki_links("[[Older]] [[Gold]] [[Old", "Old", "New"), None);
+        assert_eq!(
+            rewrite_wiki_links("[[Other]] [[Old]]", "Old", "New").as_deref(),
+            Some("[[Other]] [[New]]")
+        );
+    }
+}
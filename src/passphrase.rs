@@ -0,0 +1,115 @@
+// Passphrase prompting and passphrase based encryption.
+// The key is stretched out of the passphrase with argon2 (default params)
+// and a random salt , then XChaCha20-Poly1305 like seal.rs.
+// Keyfiles skip the stretching , they are already random.
+//
+// LIMINAL_PASSPHRASE (locked secrets) and LIMINAL_BUNDLE_PASSPHRASE
+// (identity bundles) skip the prompt so scripts and containers can run
+// without a terminal. Two of them so a service env never opens a bundle.
+
+use anyhow::{Context, Result, anyhow, bail};
+use argon2::Argon2;
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use data_encoding::BASE64;
+use serde::{Deserialize, Serialize};
+
+pub const ENV_VAR: &str = "LIMINAL_PASSPHRASE";
+pub const BUNDLE_ENV_VAR: &str = "LIMINAL_BUNDLE_PASSPHRASE";
+
+// Ask on the terminal unless env_var is set ,
+// confirm asks twice for new passphrases
+pub fn read(prompt: &str, env_var: &str, confirm: bool) -> Result<String> {
+    if let Ok(pass) = std::env::var(env_var) {
+        return Ok(pass);
+    }
+    let pass = rpassword::prompt_password(prompt).context("can't read passphrase")?;
+    if pass.is_empty() {
+        bail!("empty passphrase");
+    }
+    if confirm {
+        let again = rpassword::prompt_password("again: ").context("can't read passphrase")?;
+        if again != pass {
+            bail!("passphrases do not match");
+        }
+    }
+    Ok(pass)
+}
+
+// Encrypted blob , all base64 so it sits in json or toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Locked {
+    pub salt: String,
+    pub nonce: String,
+    pub data: String,
+}
+
+impl Locked {
+    // aad binds the data to what it is for
+    pub fn lock(passphrase: &str, plain: &[u8], aad: &[u8]) -> Result<Self> {
+        let salt: [u8; 16] = rand::random();
+        let nonce: [u8; 24] = rand::random();
+        let key = stretch(passphrase, &salt)?;
+        let data = cipher(&key)
+            .encrypt(&XNonce::from(nonce), Payload { msg: plain, aad })
+            .map_err(|_| anyhow!("encrypt failed"))?;
+        Ok(Self {
+            salt: BASE64.encode(&salt),
+            nonce: BASE64.encode(&nonce),
+            data: BASE64.encode(&data),
+        })
+    }
+
+    pub fn unlock(&self, passphrase: &str, aad: &[u8]) -> Result<Vec<u8>> {
+        let salt = BASE64.decode(self.salt.as_bytes()).context("bad salt")?;
+        let nonce = BASE64.decode(self.nonce.as_bytes()).context("bad nonce")?;
+        let nonce: [u8; 24] = nonce.try_into().map_err(|_| anyhow!("bad nonce length"))?;
+        let data = BASE64.decode(self.data.as_bytes()).context("bad data")?;
+        let key = stretch(passphrase, &salt)?;
+        cipher(&key)
+            .decrypt(&XNonce::from(nonce), Payload { msg: &data, aad })
+            .map_err(|_| anyhow!("wrong passphrase or damaged data"))
+    }
+}
+
+// Slow on purpose
+pub fn stretch(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("key stretch failed {e}"))?;
+    Ok(key)
+}
+
+pub fn keyfile_key(contents: &[u8]) -> Result<[u8; 32]> {
+    if contents.len() < 32 {
+        bail!("keyfile is too short , use at least 32 random bytes");
+    }
+    Ok(blake3::derive_key("liminal 2025 secrets keyfile", contents))
+}
+
+// Nonce on the front of the ciphertext
+pub fn seal(key: &[u8; 32], plain: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let nonce: [u8; 24] = rand::random();
+    let data = cipher(key)
+        .encrypt(&XNonce::from(nonce), Payload { msg: plain, aad })
+        .map_err(|_| anyhow!("encrypt failed"))?;
+    Ok([nonce.as_slice(), &data].concat())
+}
+
+pub fn open(key: &[u8; 32], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < 24 {
+        bail!("sealed data is too short");
+    }
+    let (nonce, data) = sealed.split_at(24);
+    let nonce: [u8; 24] = nonce.try_into()?;
+    cipher(key)
+        .decrypt(&XNonce::from(nonce), Payload { msg: data, aad })
+        .map_err(|_| anyhow!("wrong key or damaged data"))
+}
+
+fn cipher(key: &[u8; 32]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(&(*key).into())
+}
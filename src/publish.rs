@@ -0,0 +1,151 @@
+// Publish the notebook as a static html site.
+// Every live note is rendered with the site templates into a collection
+//
+// index.html
+// <id>.html
+// tags/<tag>.html
+// files/<hash>/<name>     attachments
+// static/css/...          the stylesheets from the web interface
+//
+// All links are relative so it works unpacked onto any web server. A liminal
+// node hands collection html out as downloads (see web::untrusted) , it is
+// not a place to browse the site.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use askama::Template;
+use chrono::Local;
+use iroh_blobs::{BlobsProtocol, Hash, format::collection::Collection};
+
+use crate::markdown::{self, LinkResolver};
+use crate::notes::{Note, NoteLink, Notes};
+use crate::templates::{SiteIndexTemplate, SiteNoteTemplate, SiteTagTemplate};
+use crate::web::fixed::Asset;
+
+// Site collections show up in the file browser
+pub const SITE_PREFIX: &str = "col-site-";
+
+const STYLES: [&str; 2] = ["css/bulma.min.css", "css/app.css"];
+
+struct SiteLinks<'a> {
+    root: &'a str,
+    note: &'a Note,
+    titles: &'a HashMap<String, String>,
+}
+
+impl LinkResolver for SiteLinks<'_> {
+    fn attachment(&self, hash: &str) -> String {
+        match self
+            .note
+            .attachments
+            .iter()
+            .find(|a| a.hash.to_hex().as_str() == hash)
+        {
+            Some(attachment) => attachment_path(self.root, &attachment.hash, &attachment.name),
+            None => "#".to_string(),
+        }
+    }
+
+    fn wiki(&self, target: &str) -> String {
+        match self.titles.get(&markdown::wiki_target(target).to_lowercase()) {
+            Some(id) => format!("{}{}.html", self.root, id),
+            None => "#".to_string(),
+        }
+    }
+}
+
+// Render , store and tag the site. Hands back the tag and collection hash.
+pub async fn publish(notes: &Notes, blobs: &BlobsProtocol) -> Result<(String, Hash)> {
+    let note_list = notes.get_notes().await?;
+    let titles = notes.title_index().await?;
+    let mut files: Vec<(String, Hash)> = Vec::new();
+    let mut tags: BTreeMap<String, Vec<NoteLink>> = BTreeMap::new();
+    let mut links = Vec::new();
+
+    // private notes and ones still downloading stay off the site
+    for note in note_list.iter().filter(|n| n.is_shareable()) {
+        let site_links = SiteLinks {
+            root: "",
+            note,
+            titles: &titles,
+        };
+        let page = SiteNoteTemplate {
+            root: "".to_string(),
+            note: note.clone(),
+            text: markdown::render(note.body(), &site_links),
+        }
+        .render()?;
+        files.push((format!("{}.html", note.id), add(blobs, page).await?));
+        for attachment in note.attachments.iter() {
+            files.push((
+                attachment_path("", &attachment.hash, &attachment.name),
+                attachment.hash,
+            ));
+        }
+        let link = NoteLink {
+            id: note.id.clone(),
+            title: note.title().to_string(),
+            locked: false,
+        };
+        for tag in note.meta.tags.iter() {
+            tags.entry(tag.clone()).or_default().push(link.clone());
+        }
+        links.push(link);
+    }
+
+    let mut tag_list = Vec::new();
+    for (tag, tagged) in tags {
+        let file = tag_file(&tag);
+        tag_list.push((tag.clone(), file.clone(), tagged.len()));
+        let page = SiteTagTemplate {
+            root: "../".to_string(),
+            tag: tag,
+            notes: tagged,
+        }
+        .render()?;
+        files.push((format!("tags/{file}"), add(blobs, page).await?));
+    }
+
+    let index = SiteIndexTemplate {
+        root: "".to_string(),
+        notes: links,
+        tags: tag_list,
+        published: Local::now().format("%Y-%m-%d %H:%M").to_string(),
+    }
+    .render()?;
+    files.push(("index.html".to_string(), add(blobs, index).await?));
+
+    for style in STYLES {
+        if let Some(asset) = Asset::get(style) {
+            files.push((format!("static/{style}"), add(blobs, asset.data.to_vec()).await?));
+        }
+    }
+    files.push((
+        "static/css/highlight.css".to_string(),
+        add(blobs, markdown::highlight_css()).await?,
+    ));
+
+    let col = files.into_iter().collect::<Collection>();
+    let col_hash = col.store(blobs).await?;
+    let tag = format!("{SITE_PREFIX}{}", Local::now().timestamp());
+    blobs.tags().set(tag.clone(), &col_hash).await?;
+    Ok((tag, col_hash.hash()))
+}
+
+async fn add(blobs: &BlobsProtocol, data: impl Into<bytes::Bytes>) -> Result<Hash> {
+    Ok(blobs.add_bytes(data.into()).await?.hash)
+}
+
+fn attachment_path(root: &str, hash: &Hash, name: &str) -> String {
+    format!("{root}files/{}/{}", hash.to_hex(), name)
+}
+
+// Tags can be anything , file names can't
+fn tag_file(tag: &str) -> String {
+    let name: String = tag
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("{name}.html")
+}
@@ -0,0 +1,159 @@
+// Schema versions for the redb databases.
+// Each database keeps its version in a `schema` table. On open the
+// migrations newer than that run in order inside one write transaction ,
+// so an upgrade either finishes or leaves the old tables alone.
+// A database from a newer liminal is refused rather than guessed at.
+
+use anyhow::{Context, Result, bail};
+use redb::{Database, ReadableDatabase, TableDefinition, WriteTransaction};
+
+const SCHEMA_TABLE: TableDefinition<&str, u32> = TableDefinition::new("schema");
+const VERSION_KEY: &str = "version";
+
+// Takes the database from `to - 1` to `to`
+pub struct Migration {
+    pub to: u32,
+    pub about: &'static str,
+    pub run: fn(&WriteTransaction) -> Result<()>,
+}
+
+// Databases from before versioning are 0
+pub fn version(db: &Database) -> Result<u32> {
+    let read_tx = db.begin_read()?;
+    let table = match read_tx.open_table(SCHEMA_TABLE) {
+        Ok(table) => table,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(table.get(VERSION_KEY)?.map(|v| v.value()).unwrap_or(0))
+}
+
+// Migrations have to be in order , returns the version it ends on
+pub fn migrate(db: &Database, name: &str, migrations: &[Migration]) -> Result<u32> {
+    let latest = migrations.last().map(|m| m.to).unwrap_or(0);
+    let current = version(db)?;
+    if current > latest {
+        bail!("{name} is schema version {current} , this liminal only knows up to {latest}");
+    }
+    if current == latest {
+        return Ok(current);
+    }
+    let write_tx = db.begin_write()?;
+    for migration in migrations.iter().filter(|m| m.to > current) {
+        println!("{name} migrate to {} , {}", migration.to, migration.about);
+        (migration.run)(&write_tx)
+            .with_context(|| format!("{name} migration to {} failed", migration.to))?;
+    }
+    {
+        let mut table = write_tx.open_table(SCHEMA_TABLE)?;
+        table.insert(VERSION_KEY, latest)?;
+    }
+    write_tx.commit()?;
+    Ok(latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use redb::backends::InMemoryBackend;
+
+    use super::*;
+
+    const DATA: TableDefinition<&str, u32> = TableDefinition::new("data");
+
+    // counts how many migrations ran
+    fn bump(tx: &WriteTransaction) -> Result<()> {
+        let mut table = tx.open_table(DATA)?;
+        let runs = table.get("runs")?.map(|v| v.value()).unwrap_or(0);
+        table.insert("runs", runs + 1)?;
+        Ok(())
+    }
+
+    fn broken(_tx: &WriteTransaction) -> Result<()> {
+        bail!("broken")
+    }
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            to: 1,
+            about: "first",
+            run: bump,
+        },
+        Migration {
+            to: 2,
+            about: "second",
+            run: bump,
+        },
+    ];
+
+    fn memory_db() -> Database {
+        Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap()
+    }
+
+    fn runs(db: &Database) -> u32 {
+        let read_tx = db.begin_read().unwrap();
+        match read_tx.open_table(DATA) {
+            Ok(table) => {
+                let runs = table.get("runs").unwrap().map(|v| v.value());
+                runs.unwrap_or(0)
+            }
+            Err(_) => 0,
+        }
+    }
+
+    #[test]
+    fn unversioned_runs_everything() {
+        let db = memory_db();
+        assert_eq!(version(&db).unwrap(), 0);
+        assert_eq!(migrate(&db, "test", MIGRATIONS).unwrap(), 2);
+        assert_eq!(version(&db).unwrap(), 2);
+        assert_eq!(runs(&db), 2);
+    }
+
+    #[test]
+    fn only_newer_migrations_run() {
+        let db = memory_db();
+        assert_eq!(migrate(&db, "test", &MIGRATIONS[..1]).unwrap(), 1);
+        assert_eq!(runs(&db), 1);
+        assert_eq!(migrate(&db, "test", MIGRATIONS).unwrap(), 2);
+        assert_eq!(runs(&db), 2);
+    }
+
+    #[test]
+    fn latest_is_left_alone() {
+        let db = memory_db();
+        migrate(&db, "test", MIGRATIONS).unwrap();
+        assert_eq!(migrate(&db, "test", MIGRATIONS).unwrap(), 2);
+        assert_eq!(runs(&db), 2);
+    }
+
+    #[test]
+    fn newer_is_refused() {
+        let db = memory_db();
+        migrate(&db, "test", MIGRATIONS).unwrap();
+        let err = migrate(&db, "test", &MIGRATIONS[..1]).unwrap_err();
+        assert!(err.to_string().contains("schema version 2"));
+        assert_eq!(version(&db).unwrap(), 2);
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        let db = memory_db();
+        let failing = [
+            Migration {
+                to: 1,
+                about: "first",
+                run: bump,
+            },
+            Migration {
+                to: 2,
+                about: "broken",
+                run: broken,
+            },
+        ];
+        assert!(migrate(&db, "test", &failing).is_err());
+        assert_eq!(version(&db).unwrap(), 0);
+        assert_eq!(runs(&db), 0);
+    }
+}
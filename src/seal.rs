@@ -0,0 +1,126 @@
+// Sealing for private notes.
+// The key comes out of the docs author secret , so only the node holding
+// that author can open what it sealed. Everyone else syncs and replicates
+// the ciphertext like any other entry but can't read it.
+//
+// XChaCha20-Poly1305 with a random nonce , the note id goes in as
+// associated data so a sealed body can't be moved onto another note.
+
+use std::fmt;
+
+use anyhow::{Context, Result, anyhow};
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use data_encoding::BASE64;
+use serde::{Deserialize, Serialize};
+
+const CONTEXT: &str = "liminal notes 2025 private note key";
+
+// What goes in the doc next to the note
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Sealed {
+    // author id that can open it
+    pub author: String,
+    pub nonce: String,
+    // base64 ciphertext , empty when it lives in the body blob
+    #[serde(default)]
+    pub data: String,
+}
+
+impl Sealed {
+    pub fn data(&self) -> Result<Vec<u8>> {
+        BASE64.decode(self.data.as_bytes()).context("bad sealed data")
+    }
+}
+
+#[derive(Clone)]
+pub struct SealKey([u8; 32]);
+
+// Keep the key out of the logs
+impl fmt::Debug for SealKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SealKey(..)")
+    }
+}
+
+impl SealKey {
+    pub fn derive(secret: &[u8; 32]) -> Self {
+        Self(blake3::derive_key(CONTEXT, secret))
+    }
+
+    // Hands back the nonce and the ciphertext
+    pub fn seal(&self, id: &str, plain: &[u8]) -> Result<(String, Vec<u8>)> {
+        let nonce: [u8; 24] = rand::random();
+        let payload = Payload {
+            msg: plain,
+            aad: id.as_bytes(),
+        };
+        let data = self
+            .cipher()
+            .encrypt(&XNonce::from(nonce), payload)
+            .map_err(|_| anyhow!("seal failed"))?;
+        Ok((BASE64.encode(&nonce), data))
+    }
+
+    pub fn open(&self, id: &str, nonce: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let nonce = BASE64.decode(nonce.as_bytes()).context("bad nonce")?;
+        let nonce: [u8; 24] = nonce.try_into().map_err(|_| anyhow!("bad nonce length"))?;
+        let payload = Payload {
+            msg: data,
+            aad: id.as_bytes(),
+        };
+        self.cipher()
+            .decrypt(&XNonce::from(nonce), payload)
+            .map_err(|_| anyhow!("can't open {id}"))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let key = SealKey::derive(&[7; 32]);
+        let (nonce, data) = key.seal("note", b"hello").unwrap();
+        assert_ne!(data, b"hello");
+        assert_eq!(key.open("note", &nonce, &data).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn other_authors_cant_open() {
+        let key = SealKey::derive(&[7; 32]);
+        let other = SealKey::derive(&[8; 32]);
+        let (nonce, data) = key.seal("note", b"hello").unwrap();
+        assert!(other.open("note", &nonce, &data).is_err());
+    }
+
+    #[test]
+    fn bound_to_the_note() {
+        let key = SealKey::derive(&[7; 32]);
+        let (nonce, mut data) = key.seal("note", b"hello").unwrap();
+        assert!(key.open("other", &nonce, &data).is_err());
+        data[0] ^= 1;
+        assert!(key.open("note", &nonce, &data).is_err());
+        assert!(key.open("note", "bad", &data).is_err());
+    }
+
+    #[test]
+    fn sealed_data_decodes() {
+        let key = SealKey::derive(&[7; 32]);
+        let (nonce, data) = key.seal("note", b"hello").unwrap();
+        let sealed = Sealed {
+            author: "me".to_string(),
+            nonce,
+            data: BASE64.encode(&data),
+        };
+        let opened = key.open("note", &sealed.nonce, &sealed.data().unwrap());
+        assert_eq!(opened.unwrap(), b"hello");
+    }
+}
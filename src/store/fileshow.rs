@@ -4,6 +4,7 @@ use std::{hash, path::PathBuf, str::FromStr, sync::Arc};
 
 use anyhow::{Result, anyhow};
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use fs_tree::FsTree;
 use iroh_blobs::{
@@ -20,6 +21,8 @@ pub struct FileSet(Arc<Inner>);
 pub struct Inner {
     blobs: BlobsProtocol,
     roots: DashMap<String, Item>,
+    // when each root turned up , for the feed
+    arrived: DashMap<String, i64>,
 }
 
 // Internal representation
@@ -46,6 +49,7 @@ impl FileSet {
         Self(Arc::new(Inner {
             blobs: blobs,
             roots: DashMap::new(),
+            arrived: DashMap::new(),
         }))
     }
 
@@ -67,6 +71,8 @@ impl FileSet {
             if !self.0.roots.contains_key(&tag_name) {
                 //let tag_name = tag.name.to_string();
                 // println!("{}", &tag_name);
+                let arrived = tag_time(&tag_name).unwrap_or_else(|| Utc::now().timestamp());
+                self.0.arrived.insert(tag_name.clone(), arrived);
                 self.0
                     .roots
                     .insert(tag_name, Item::Unloaded { hash: tag.hash });
@@ -194,6 +200,25 @@ impl FileSet {
         Ok(None)
     }
 
+    // Roots with their hash and arrival time , newest first
+    pub fn recent(&self) -> Vec<(String, Hash, i64)> {
+        let mut items: Vec<(String, Hash, i64)> = self
+            .0
+            .roots
+            .iter()
+            .map(|root| {
+                let hash = match root.value() {
+                    Item::Unloaded { hash } => *hash,
+                    Item::Loaded { hash, .. } => *hash,
+                };
+                let arrived = self.0.arrived.get(root.key()).map(|a| *a).unwrap_or_default();
+                (root.key().to_string(), hash, arrived)
+            })
+            .collect();
+        items.sort_by_key(|(_, _, arrived)| std::cmp::Reverse(*arrived));
+        items
+    }
+
     pub fn list_roots(&self) -> Vec<String> {
         let mut items: Vec<String> = self.0.roots.iter().map(|k| k.key().to_string()).collect();
         items.sort();
@@ -201,3 +226,14 @@ impl FileSet {
         items
     }
 }
+
+// Most tags carry the time they were made , col-<rfc3339> or col-site-<unix>
+fn tag_time(name: &str) -> Option<i64> {
+    name.match_indices('-').find_map(|(index, _)| {
+        let stamp = &name[index + 1..];
+        match DateTime::parse_from_rfc3339(stamp) {
+            Ok(dt) => Some(dt.timestamp()),
+            Err(_) => stamp.parse::<i64>().ok(),
+        }
+    })
+}
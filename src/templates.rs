@@ -3,7 +3,10 @@
 use askama::Template;
 use askama_web::WebTemplate;
 
-use crate::notes::Note;
+use crate::import::ImportReport;
+use crate::markdown::Task;
+use crate::notes::{Conflict, Note, NoteLink, Snapshot};
+use crate::web::feeds::FeedEntry;
 
 #[derive(Template, WebTemplate)]
 #[template(path = "index.html")]
@@ -28,6 +31,17 @@ pub struct FilePageTemplate {
     pub ticket: Option<String>
 }
 
+#[derive(Template, WebTemplate)]
+#[template(path = "markdown.html")]
+pub struct MarkdownPageTemplate {
+    pub title: String,
+    pub text: String,
+    pub path: String,
+    pub segments: Vec<String>,
+    pub prefixes: Vec<String>,
+    pub section: String,
+}
+
 #[derive(Template, WebTemplate)]
 #[template(path = "collection.html")]
 pub struct CollectionPageTemplate {
@@ -44,7 +58,12 @@ pub struct CollectionPageTemplate {
 #[derive(Template, WebTemplate)]
 #[template(path = "notes/notes.html")]
 pub struct NotesPageTemplate {
-    pub notes: Vec<String>,
+    pub notes: Vec<NoteLink>,
+    pub error: Option<String>,
+    // download policy name , label and if it is the current one
+    pub downloads: Vec<(String, String, bool)>,
+    pub tags: Vec<(String, usize)>,
+    pub tag: Option<String>,
     pub ticket: Option<String>,
     pub section: String,
 }
@@ -55,24 +74,79 @@ pub struct NotePageTemplate {
     pub note: Note,
     pub text: String, 
     pub section: String,
-    pub notes: Vec<String>,
+    pub notes: Vec<NoteLink>,
 }
 
 #[derive(Template, WebTemplate)]
 #[template(path = "notes/create.html")]
 pub struct NoteCreateTemplate {
     pub section: String,
+    pub title: String,
     pub title_error: bool,
     pub text: String,
-    pub notes: Vec<String>,
+    pub notes: Vec<NoteLink>,
 }
 
 #[derive(Template, WebTemplate)]
 #[template(path = "notes/edit.html")]
 pub struct NoteEditTemplate {
     pub note: Note,
+    pub base: String,
     pub section: String,
-    pub notes: Vec<String>,
+    pub notes: Vec<NoteLink>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "notes/conflict.html")]
+pub struct NoteConflictTemplate {
+    pub conflict: Conflict,
+    pub head: String,
+    pub section: String,
+    pub notes: Vec<NoteLink>,
+}
+#[derive(Template, WebTemplate)]
+#[template(path = "notes/trash.html")]
+pub struct NotesTrashTemplate {
+    pub trash: Vec<Note>,
+    pub trash_days: u64,
+    pub section: String,
+    pub notes: Vec<NoteLink>,
+}
+#[derive(Template, WebTemplate)]
+#[template(path = "notes/snapshots.html")]
+pub struct NotesSnapshotsTemplate {
+    pub snapshots: Vec<Snapshot>,
+    pub section: String,
+    pub notes: Vec<NoteLink>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "notes/snapshot.html")]
+pub struct NoteSnapshotTemplate {
+    pub tag: String,
+    pub items: Vec<NoteLink>,
+    pub section: String,
+    pub notes: Vec<NoteLink>,
+}
+#[derive(Template, WebTemplate)]
+#[template(path = "notes/tasks.html")]
+pub struct NotesTasksTemplate {
+    // note , task and the checkbox html
+    pub tasks: Vec<(NoteLink, Task, String)>,
+    pub section: String,
+    pub notes: Vec<NoteLink>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "notes/import.html")]
+pub struct NotesImportTemplate {
+    pub collections: Vec<String>,
+    // directory imports are off without one
+    pub import_root: Option<String>,
+    pub report: Option<ImportReport>,
+    pub error: Option<String>,
+    pub section: String,
+    pub notes: Vec<NoteLink>,
 }
 // End notes interface
 
@@ -91,6 +165,33 @@ pub struct NoteEditTemplate {
 // }
 
 
+// Published static site , not served by rocket
+// root is the relative path back to the top of the site
+#[derive(Template)]
+#[template(path = "site/index.html")]
+pub struct SiteIndexTemplate {
+    pub root: String,
+    pub notes: Vec<NoteLink>,
+    pub tags: Vec<(String, String, usize)>,
+    pub published: String,
+}
+
+#[derive(Template)]
+#[template(path = "site/note.html")]
+pub struct SiteNoteTemplate {
+    pub root: String,
+    pub note: Note,
+    pub text: String,
+}
+
+#[derive(Template)]
+#[template(path = "site/tag.html")]
+pub struct SiteTagTemplate {
+    pub root: String,
+    pub tag: String,
+    pub notes: Vec<NoteLink>,
+}
+
 // 3d viewer 
 #[derive(Template, WebTemplate)]
 #[template(path = "gltfview.html")]
@@ -119,6 +220,42 @@ pub struct IconsPageTemplate {
 #[template(path = "admin.html")]
 pub struct AdminPageTemplate {
     pub section: String,
+    // name and url
+    pub feeds: Vec<(String, String)>,
+    // name and value
+    pub settings: Vec<(String, String)>,
+    // name and hex key
+    pub docs: Vec<(String, String)>,
+    pub authors: Vec<(String, String)>,
+    // node id and status
+    pub nodes: Vec<(String, String)>,
+    // from the id store , with profiles
+    pub peers: Vec<PeerView>,
+}
+
+// One id store row for the admin page
+pub struct PeerView {
+    // profile nickname , or "_"
+    pub name: String,
+    pub id: String,
+    // the start of the id , names aren't unique
+    pub short_id: String,
+    pub status: String,
+    pub last_seen: String,
+    pub contact: String,
+    pub capabilities: String,
+}
+
+// Rendered by hand in web::feeds for the atom content type
+#[derive(Template)]
+#[template(path = "feeds/atom.xml")]
+pub struct AtomFeedTemplate {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub self_link: String,
+    pub updated: String,
+    pub entries: Vec<FeedEntry>,
 }
 
 
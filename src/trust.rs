@@ -0,0 +1,219 @@
+// Web of trust.
+// A vouch is a signed "I trust this id" (or a revoke) , sent round on the
+// announce topic and kept in the id store , latest per voucher and subject.
+// Trust starts at this node and the peers marked fren. Each round , anyone
+// with at least `threshold` vouches from the trusted set joins it , up to
+// `depth` rounds out. Seen and unknown peers that make it in are promoted
+// to Known. Nothing is demoted again automatically , that is left to the
+// admin commands.
+// Only vouches from the trusted set are kept , so strangers can't fill
+// the store. The announcer recomputes once a burst of new vouches settles.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Result, bail};
+use iroh::{EndpointId, SecretKey};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::announce::{Announcement, SignedAnnouncement};
+use crate::id_store::{Fren, IdClient, Status, Vouch};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrustConfig {
+    // rounds out from our frens , 0 turns promotion off
+    pub depth: u32,
+    // trusted vouches needed to be trusted
+    pub threshold: usize,
+}
+
+impl Default for TrustConfig {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            threshold: 2,
+        }
+    }
+}
+
+// Signed by us , ready to store and broadcast
+pub fn sign(secret: &SecretKey, subject: EndpointId, trusted: bool) -> Result<Vouch> {
+    if secret.public() == subject {
+        bail!("can't vouch for yourself");
+    }
+    let timestamp = chrono::Utc::now().timestamp();
+    let announcement = Announcement::Vouch {
+        subject,
+        trusted,
+        timestamp,
+    };
+    let signed = SignedAnnouncement::sign_and_encode(secret, &announcement)?;
+    Ok(Vouch {
+        voucher: secret.public(),
+        subject,
+        trusted,
+        timestamp,
+        signed: signed.to_vec(),
+    })
+}
+
+// This node , its frens and everyone vouched in from there
+pub fn trusted(
+    me: EndpointId,
+    frens: &[Fren],
+    vouches: &[Vouch],
+    config: &TrustConfig,
+) -> HashSet<EndpointId> {
+    let status: HashMap<EndpointId, &Status> = frens.iter().map(|f| (f.id(), f.status())).collect();
+    let hostile = |id: &EndpointId| matches!(status.get(id), Some(Status::Enemy | Status::DestroyOnSight));
+
+    let mut trusted: HashSet<EndpointId> = frens
+        .iter()
+        .filter(|f| matches!(f.status(), Status::Fren))
+        .map(|f| f.id())
+        .collect();
+    trusted.insert(me);
+
+    for _ in 0..config.depth {
+        let mut counts: HashMap<EndpointId, usize> = HashMap::new();
+        for vouch in vouches {
+            if vouch.trusted && trusted.contains(&vouch.voucher) && !trusted.contains(&vouch.subject) {
+                *counts.entry(vouch.subject).or_default() += 1;
+            }
+        }
+        let joined: Vec<EndpointId> = counts
+            .into_iter()
+            .filter(|(id, count)| *count >= config.threshold && !hostile(id))
+            .map(|(id, _)| id)
+            .collect();
+        if joined.is_empty() {
+            break;
+        }
+        trusted.extend(joined);
+    }
+    trusted
+}
+
+// Who should go up to Known
+pub fn promotions(me: EndpointId, frens: &[Fren], vouches: &[Vouch], config: &TrustConfig) -> Vec<EndpointId> {
+    let status: HashMap<EndpointId, &Status> = frens.iter().map(|f| (f.id(), f.status())).collect();
+    trusted(me, frens, vouches, config)
+        .into_iter()
+        .filter(|id| *id != me && matches!(status.get(id), None | Some(Status::Seen)))
+        .collect()
+}
+
+// Recompute from the store and promote , hands back the trusted set
+pub async fn apply(ids: &IdClient, me: EndpointId, config: &TrustConfig) -> Result<HashSet<EndpointId>> {
+    let frens = ids.list().await?;
+    let vouches = ids.list_vouches().await?;
+    for id in promotions(me, &frens, &vouches, config) {
+        ids.set_status(id, Status::Known).await?;
+        info!("{} promoted to known by vouches", id);
+    }
+    Ok(trusted(me, &frens, &vouches, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u8) -> EndpointId {
+        SecretKey::from_bytes(&[n; 32]).public()
+    }
+
+    fn vouch(voucher: u8, subject: u8, trusted: bool) -> Vouch {
+        Vouch {
+            voucher: id(voucher),
+            subject: id(subject),
+            trusted,
+            timestamp: 0,
+            signed: vec![],
+        }
+    }
+
+    fn config(depth: u32, threshold: usize) -> TrustConfig {
+        TrustConfig { depth, threshold }
+    }
+
+    // 1 and 2 are frens of this node (0)
+    fn frens() -> Vec<Fren> {
+        vec![
+            Fren::with_status(id(1), Status::Fren),
+            Fren::with_status(id(2), Status::Fren),
+        ]
+    }
+
+    fn sorted(mut ids: Vec<EndpointId>) -> Vec<EndpointId> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn threshold() {
+        let vouches = [vouch(1, 5, true), vouch(2, 5, true), vouch(1, 6, true)];
+        let promoted = promotions(id(0), &frens(), &vouches, &config(1, 2));
+        assert_eq!(promoted, vec![id(5)]);
+        let promoted = promotions(id(0), &frens(), &vouches, &config(1, 1));
+        assert_eq!(sorted(promoted), sorted(vec![id(5), id(6)]));
+    }
+
+    #[test]
+    fn depth() {
+        // 5 comes in from the frens , 5 and 1 bring in 6
+        let vouches = [
+            vouch(1, 5, true),
+            vouch(2, 5, true),
+            vouch(5, 6, true),
+            vouch(1, 6, true),
+        ];
+        let promoted = promotions(id(0), &frens(), &vouches, &config(1, 2));
+        assert_eq!(promoted, vec![id(5)]);
+        let promoted = promotions(id(0), &frens(), &vouches, &config(2, 2));
+        assert_eq!(sorted(promoted), sorted(vec![id(5), id(6)]));
+        assert!(promotions(id(0), &frens(), &vouches, &config(0, 2)).is_empty());
+    }
+
+    #[test]
+    fn strangers_dont_count() {
+        let vouches = [vouch(1, 5, true), vouch(7, 5, true), vouch(8, 5, true)];
+        assert!(promotions(id(0), &frens(), &vouches, &config(1, 2)).is_empty());
+    }
+
+    #[test]
+    fn hostile_stay_out() {
+        let mut frens = frens();
+        frens.push(Fren::with_status(id(5), Status::Enemy));
+        frens.push(Fren::with_status(id(6), Status::DestroyOnSight));
+        let vouches = [
+            vouch(1, 5, true),
+            vouch(2, 5, true),
+            vouch(1, 6, true),
+            vouch(2, 6, true),
+        ];
+        assert!(promotions(id(0), &frens, &vouches, &config(1, 2)).is_empty());
+        assert!(!trusted(id(0), &frens, &vouches, &config(1, 2)).contains(&id(5)));
+    }
+
+    #[test]
+    fn revokes_dont_count() {
+        let vouches = [vouch(1, 5, true), vouch(2, 5, false)];
+        assert!(promotions(id(0), &frens(), &vouches, &config(1, 2)).is_empty());
+    }
+
+    #[test]
+    fn self_and_known_left_alone() {
+        let mut frens = frens();
+        frens.push(Fren::with_status(id(5), Status::Known));
+        let vouches = [
+            vouch(1, 0, true),
+            vouch(2, 0, true),
+            vouch(1, 5, true),
+            vouch(2, 5, true),
+        ];
+        assert!(promotions(id(0), &frens, &vouches, &config(1, 2)).is_empty());
+        let trusted = trusted(id(0), &frens, &vouches, &config(1, 2));
+        assert!(trusted.contains(&id(0)) && trusted.contains(&id(5)));
+    }
+}
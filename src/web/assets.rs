@@ -2,14 +2,17 @@
 //!
 
 use crate::{
+    front_matter::FrontMatter,
+    markdown::{self, LinkResolver},
     store::{FileSet, RenderType},
-    templates::{CollectionPageTemplate, FilePageTemplate},
+    templates::{CollectionPageTemplate, FilePageTemplate, MarkdownPageTemplate},
+    web::untrusted::Untrusted,
 };
 use chrono::Local;
 use iroh::Endpoint;
 use iroh_blobs::ticket::BlobTicket;
 use iroh_blobs::{BlobFormat, BlobsProtocol};
-use rocket::response::Responder;
+use rocket::response::{Redirect, Responder};
 use rocket::routes;
 use rocket::{State, fairing::AdHoc};
 use std::path::PathBuf;
@@ -41,6 +44,30 @@ fn split_path(path: &PathBuf) -> (Vec<String>, Vec<String>) {
     (prefixes, items)
 }
 
+// Markdown inside a collection , links stay inside the collection
+struct CollectionLinks;
+
+impl LinkResolver for CollectionLinks {
+    fn attachment(&self, _hash: &str) -> String {
+        "#".to_string()
+    }
+
+    // sibling file in the same folder
+    fn wiki(&self, target: &str) -> String {
+        match target.ends_with(".md") {
+            true => target.to_string(),
+            false => format!("{target}.md"),
+        }
+    }
+}
+
+#[derive(Responder)]
+pub enum FileView {
+    Folder(FilePageTemplate),
+    Markdown(MarkdownPageTemplate),
+    Raw(Redirect),
+}
+
 #[get("/files")]
 pub async fn files<'r>(fileset: &State<FileSet>) -> impl Responder<'r, 'static> {
     let coll = fileset.list_roots();
@@ -119,26 +146,46 @@ pub async fn inner_files<'r>(
     collection: &str,
     path: PathBuf,
     fileset: &State<FileSet>,
-) -> impl Responder<'r, 'static> {
+) -> Result<FileView, ()> {
     let res = fileset.get(collection.to_string(), &path).await;
+    let mut full_path = PathBuf::new();
+    full_path.push(&collection);
+    full_path.push(&path);
     match res {
         Ok(res) => {
             if let Some(item) = res {
                 match item {
-                    RenderType::File { file_name: _ } => todo!(),
+                    RenderType::File { file_name } => {
+                        if !file_name.ends_with(".md") {
+                            let uri = format!("/asset/{}", full_path.display());
+                            return Ok(FileView::Raw(Redirect::to(uri)));
+                        }
+                        let Ok(Some(data)) = fileset.get_file(collection.to_string(), &path).await
+                        else {
+                            return Err(());
+                        };
+                        let text = String::from_utf8_lossy(&data);
+                        let (meta, body) = FrontMatter::parse(&text);
+                        let (pref, entries) = split_path(&full_path);
+                        return Ok(FileView::Markdown(MarkdownPageTemplate {
+                            title: meta.title.unwrap_or(file_name),
+                            text: markdown::render(body, &CollectionLinks),
+                            path: full_path.display().to_string(),
+                            segments: entries,
+                            prefixes: pref,
+                            section: "files".to_string(),
+                        }));
+                    }
                     RenderType::Folder { items } => {
-                        let mut full_path = PathBuf::new();
-                        full_path.push(&collection);
-                        full_path.push(&path);
                         let (pref, entries) = split_path(&full_path);
-                        return Ok(FilePageTemplate {
+                        return Ok(FileView::Folder(FilePageTemplate {
                             items: items,
                             path: full_path.display().to_string(),
                             segments: entries,
                             prefixes: pref,
                             section: "files".to_string(),
                             ticket: None,
-                        });
+                        }));
                     }
                 }
             } else {
@@ -149,14 +196,16 @@ pub async fn inner_files<'r>(
     }
 }
 
+// Raw file out of a collection , collections come from peers so
+// it goes out sandboxed and anything but an image is a download
 #[get("/asset/<root>/<path..>", rank = 2)]
 pub async fn asset_file<'r>(
     root: &str,
     path: PathBuf,
     fileset: &State<FileSet>,
-) -> impl Responder<'r, 'static> {
-    if let Ok(_data) = fileset.get_file(root.to_string(), &path).await {
-        return Ok(());
-    }
-    Err(())
+) -> Option<Untrusted> {
+    // expand the collection if it has not been looked at yet
+    let _ = fileset.get(root.to_string(), &path).await;
+    let data = fileset.get_file(root.to_string(), &path).await.ok()??;
+    Some(Untrusted::new(&path.to_string_lossy(), data.to_vec()))
 }
@@ -36,6 +36,7 @@ pub async fn login_post<'r>(
 
 pub struct User {
     pub id: u64,
+    pub name: String,
 }
 
 // TODO fix
@@ -46,7 +47,10 @@ impl<'r> FromRequest<'r> for User {
     async fn from_request(request: &'r Request<'_>) -> Outcome<User, ()> {
         let val = request.cookies().get_private("user_id");
         match val {
-            Some(_) => Outcome::Success(User { id: 0 }),
+            Some(cookie) => Outcome::Success(User {
+                id: 0,
+                name: cookie.value().to_string(),
+            }),
             None => Outcome::Forward(Status::Unauthorized),
         }
     }
@@ -0,0 +1,204 @@
+// Atom feeds of recent notes and collections.
+// Feed readers don't have the session cookie so each user gets a token
+// in the url instead. The token is a keyed blake3 hash (a MAC) of the user
+// name with a key derived from the cookie secret, nothing to store and
+// changing the cookie secret changes every token.
+
+use askama::Template;
+use chrono::{DateTime, Utc};
+use rocket::State;
+use rocket::fairing::AdHoc;
+use rocket::http::ContentType;
+use rocket::http::uri::Host;
+
+use crate::notes::Notes;
+use crate::store::FileSet;
+use crate::templates::AtomFeedTemplate;
+
+const FEED_LEN: usize = 50;
+const SUMMARY_LEN: usize = 280;
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Feeds", |rocket| async {
+        rocket.mount("/", routes![notes_feed, collections_feed])
+    })
+}
+
+#[derive(Clone)]
+pub struct FeedKey([u8; 32]);
+
+impl FeedKey {
+    pub fn new(secret: &[u8; 32]) -> Self {
+        Self(blake3::derive_key("liminal feeds", secret))
+    }
+
+    fn mac(&self, user: &str) -> blake3::Hash {
+        blake3::keyed_hash(&self.0, user.as_bytes())
+    }
+
+    pub fn token(&self, user: &str) -> String {
+        self.mac(user).to_hex().to_string()
+    }
+
+    // blake3::Hash compares in constant time , so compare those not strings
+    fn check(&self, user: &str, token: &str) -> bool {
+        match blake3::Hash::from_hex(token) {
+            Ok(token) => self.mac(user) == token,
+            Err(_) => false,
+        }
+    }
+
+    // Feed urls for the admin page , name and path
+    pub fn links(&self, user: &str) -> Vec<(String, String)> {
+        let token = self.token(user);
+        vec![
+            ("Notes".to_string(), format!("/feeds/{user}/{token}/notes.atom")),
+            (
+                "Collections".to_string(),
+                format!("/feeds/{user}/{token}/collections.atom"),
+            ),
+        ]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FeedEntry {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub published: String,
+    pub updated: String,
+    pub summary: String,
+}
+
+#[get("/feeds/<user>/<token>/notes.atom")]
+pub async fn notes_feed(
+    user: &str,
+    token: &str,
+    host: &Host<'_>,
+    key: &State<FeedKey>,
+    notes: &State<Notes>,
+) -> Option<(ContentType, String)> {
+    if !key.check(user, token) {
+        warn!("bad feed token for {}", user);
+        return None;
+    }
+    let base = format!("http://{host}");
+    // get_notes is newest first already
+    let entries = match notes.get_notes().await {
+        // feed urls get passed around , leave private notes out
+        Ok(note_list) => note_list
+            .into_iter()
+            .filter(|note| !note.private && !note.locked)
+            .take(FEED_LEN)
+            .map(|note| FeedEntry {
+                id: format!("urn:liminal:note:{}", note.id),
+                title: note.path(),
+                link: format!("{base}/notes/show/{}", note.id),
+                published: rfc3339(note.created),
+                updated: rfc3339(note.updated),
+                summary: summary(note.body()),
+            })
+            .collect(),
+        Err(e) => {
+            warn!("notes feed failed {:?}", e);
+            vec![]
+        }
+    };
+    let feed = AtomFeedTemplate {
+        id: format!("urn:liminal:notes:{host}"),
+        title: "Liminal notes".to_string(),
+        link: format!("{base}/notes"),
+        self_link: format!("{base}/feeds/{user}/{token}/notes.atom"),
+        updated: latest(&entries),
+        entries: entries,
+    };
+    render(feed)
+}
+
+#[get("/feeds/<user>/<token>/collections.atom")]
+pub async fn collections_feed(
+    user: &str,
+    token: &str,
+    host: &Host<'_>,
+    key: &State<FeedKey>,
+    file_set: &State<FileSet>,
+) -> Option<(ContentType, String)> {
+    if !key.check(user, token) {
+        warn!("bad feed token for {}", user);
+        return None;
+    }
+    let base = format!("http://{host}");
+    // pick up anything that has arrived since the last look
+    file_set.fill("col").await;
+    let entries: Vec<FeedEntry> = file_set
+        .recent()
+        .into_iter()
+        .take(FEED_LEN)
+        .map(|(name, hash, arrived)| FeedEntry {
+            id: format!("urn:liminal:collection:{}", hash.to_hex()),
+            link: format!("{base}/files/{name}"),
+            published: rfc3339(arrived),
+            updated: rfc3339(arrived),
+            summary: hash.to_hex().to_string(),
+            title: name,
+        })
+        .collect();
+    let feed = AtomFeedTemplate {
+        id: format!("urn:liminal:collections:{host}"),
+        title: "Liminal collections".to_string(),
+        link: format!("{base}/files"),
+        self_link: format!("{base}/feeds/{user}/{token}/collections.atom"),
+        updated: latest(&entries),
+        entries: entries,
+    };
+    render(feed)
+}
+
+fn render(feed: AtomFeedTemplate) -> Option<(ContentType, String)> {
+    match feed.render() {
+        Ok(xml) => Some((ContentType::new("application", "atom+xml"), xml)),
+        Err(e) => {
+            warn!("feed render failed {:?}", e);
+            None
+        }
+    }
+}
+
+// Entries are newest first , an empty feed is as new as now
+fn latest(entries: &[FeedEntry]) -> String {
+    match entries.iter().map(|e| &e.updated).max() {
+        Some(updated) => updated.clone(),
+        None => Utc::now().to_rfc3339(),
+    }
+}
+
+fn rfc3339(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+// First bit of the markdown , cut on a char boundary
+fn summary(text: &str) -> String {
+    let text = text.trim();
+    match text.char_indices().nth(SUMMARY_LEN) {
+        Some((index, _)) => format!("{}…", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_check_per_user() {
+        let key = FeedKey::new(&[7u8; 32]);
+        let token = key.token("alice");
+        assert!(key.check("alice", &token));
+        assert!(!key.check("bob", &token));
+        assert!(!key.check("alice", "not hex"));
+        assert!(!FeedKey::new(&[8u8; 32]).check("alice", &token));
+    }
+}
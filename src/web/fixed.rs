@@ -9,6 +9,12 @@ use rust_embed::{Embed, EmbeddedFile};
 use std::ffi::OsStr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::LazyLock;
+
+use crate::markdown;
+
+// Generated once from the syntect theme
+static HIGHLIGHT_CSS: LazyLock<String> = LazyLock::new(markdown::highlight_css);
 
 #[derive(Embed)]
 #[folder = "static/web/"]
@@ -53,6 +59,12 @@ pub fn dist(file: PathBuf) -> Option<CacheControl> {
     Some(CacheControl::new(content_type, asset))
 }
 
+// Colours for the highlighted code blocks in notes
+#[get("/css/highlight.css")]
+pub fn highlight_css() -> (ContentType, &'static str) {
+    (ContentType::CSS, HIGHLIGHT_CSS.as_str())
+}
+
 #[get("/favicon.ico")]
 pub fn favicon() -> Option<CacheControl> {
     let file = PathBuf::from_str("img/favicon.ico").unwrap();
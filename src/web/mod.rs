@@ -1,28 +1,33 @@
 //! Base web interface.
 //! TODO : make this per user.
 
-use std::str::FromStr;
+use std::{collections::HashMap, str::FromStr};
 
+use crate::config::{ConfigClient, KeyTable};
+use crate::id_store::{IdClient, Profile};
 use crate::store::FileSet;
-use crate::templates::{AdminPageTemplate, GltfPageTemplate, HomePageTemplate, IconsPageTemplate};
+use crate::templates::{AdminPageTemplate, GltfPageTemplate, HomePageTemplate, IconsPageTemplate, PeerView};
 use crate::web::auth::User;
 use chrono::Local;
-use iroh::Endpoint;
+use data_encoding::HEXLOWER_PERMISSIVE;
+use iroh::{Endpoint, EndpointId};
 use iroh_blobs::ticket::BlobTicket;
 use iroh_blobs::{BlobsProtocol, HashAndFormat};
 use rocket::State;
 use rocket::fairing::AdHoc;
 use rocket::form::Form;
 use rocket::get;
-use rocket::response::Responder;
+use rocket::response::{Redirect, Responder};
 
 pub mod assets;
 pub mod auth;
+pub mod feeds;
 pub mod fixed;
 pub mod notes;
 pub mod replica;
 pub mod search;
 pub mod services;
+pub mod untrusted;
 
 // Run these things
 pub(crate) fn stage() -> AdHoc {
@@ -34,11 +39,14 @@ pub(crate) fn stage() -> AdHoc {
                 ticket,
                 fixed::dist,
                 fixed::favicon,
+                fixed::highlight_css,
                 viewer,
                 auth::login,
                 auth::login_post,
                 show_icons,
                 admin_page,
+                admin_setting,
+                admin_key,
                 search::base_search,
                 search::searcher,
                 search::search_page,
@@ -55,11 +63,153 @@ pub async fn index<'r>(_user: User) -> impl Responder<'r, 'static> {
 }
 
 #[get("/admin")]
-pub async fn admin_page<'r>(user: User) -> impl Responder<'r, 'static> {
+pub async fn admin_page<'r>(
+    user: User,
+    feed_key: &State<feeds::FeedKey>,
+    conf: &State<ConfigClient>,
+    ids: &State<IdClient>,
+) -> impl Responder<'r, 'static> {
     info!("{:?}",user.id);
+    let settings = conf.list_settings().await.unwrap_or_else(|e| {
+        warn!("settings list failed {:?}", e);
+        vec![]
+    });
+    let nodes = match conf.list_nodes().await {
+        Ok(nodes) => nodes
+            .into_iter()
+            .map(|(node, status)| (node.to_string(), status))
+            .collect(),
+        Err(e) => {
+            warn!("node list failed {:?}", e);
+            vec![]
+        }
+    };
     AdminPageTemplate {
         section: "admin".to_string(),
+        feeds: feed_key.links(&user.name),
+        settings: settings,
+        docs: hex_keys(conf, KeyTable::Docs).await,
+        authors: hex_keys(conf, KeyTable::Authors).await,
+        nodes: nodes,
+        peers: peers(ids).await,
+    }
+}
+
+// characters of the endpoint id shown next to the name
+const SHORT_ID: usize = 10;
+
+// Named from their profiles where we have one
+async fn peers(ids: &IdClient) -> Vec<PeerView> {
+    let frens = match ids.list().await {
+        Ok(frens) => frens,
+        Err(e) => {
+            warn!("peer list failed {:#}", e);
+            return vec![];
+        }
+    };
+    let profiles: HashMap<EndpointId, Profile> = match ids.list_profiles().await {
+        Ok(profiles) => profiles.into_iter().map(|p| (p.id, p)).collect(),
+        Err(e) => {
+            warn!("profile list failed {:#}", e);
+            HashMap::new()
+        }
+    };
+    frens
+        .into_iter()
+        .map(|fren| {
+            let profile = profiles.get(&fren.id());
+            let last_seen = match chrono::DateTime::from_timestamp(fren.last_seen(), 0) {
+                Some(time) if fren.last_seen() > 0 => {
+                    time.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string()
+                }
+                _ => "never".to_string(),
+            };
+            let id = fren.id().to_string();
+            PeerView {
+                name: fren.name().to_string(),
+                short_id: id.chars().take(SHORT_ID).collect(),
+                id,
+                status: format!("{:?}", fren.status()),
+                last_seen,
+                contact: profile.and_then(|p| p.contact.clone()).unwrap_or_default(),
+                capabilities: profile.map(|p| p.capabilities.join(", ")).unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+async fn hex_keys(conf: &ConfigClient, table: KeyTable) -> Vec<(String, String)> {
+    match conf.list_keys(table).await {
+        Ok(keys) => keys
+            .into_iter()
+            .map(|(name, key)| (name, HEXLOWER_PERMISSIVE.encode(&key)))
+            .collect(),
+        Err(e) => {
+            warn!("{:?} list failed {:?}", table, e);
+            vec![]
+        }
+    }
+}
+
+#[derive(FromForm)]
+pub struct SettingForm<'v> {
+    name: &'v str,
+    // empty removes it
+    value: &'v str,
+}
+
+#[post("/admin/setting", data = "<form>")]
+pub async fn admin_setting(
+    _user: User,
+    form: Form<SettingForm<'_>>,
+    conf: &State<ConfigClient>,
+) -> Redirect {
+    let name = form.name.trim();
+    if !name.is_empty() {
+        if let Err(e) = conf.set_setting(name, form.value.trim()).await {
+            warn!("setting {} failed {:?}", name, e);
+        }
+    }
+    Redirect::to(uri!(admin_page))
+}
+
+#[derive(FromForm)]
+pub struct KeyForm<'v> {
+    // docs or authors
+    table: &'v str,
+    name: &'v str,
+    // 32 bytes of hex
+    key: &'v str,
+}
+
+#[post("/admin/key", data = "<form>")]
+pub async fn admin_key(
+    _user: User,
+    form: Form<KeyForm<'_>>,
+    conf: &State<ConfigClient>,
+) -> Redirect {
+    let table = match form.table {
+        "docs" => KeyTable::Docs,
+        "authors" => KeyTable::Authors,
+        other => {
+            warn!("unknown key table {}", other);
+            return Redirect::to(uri!(admin_page));
+        }
+    };
+    let name = form.name.trim();
+    let key: Option<[u8; 32]> = HEXLOWER_PERMISSIVE
+        .decode(form.key.trim().as_bytes())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok());
+    match key {
+        Some(key) if !name.is_empty() => {
+            if let Err(e) = conf.set_key(table, name, key).await {
+                warn!("key {} failed {:?}", name, e);
+            }
+        }
+        _ => warn!("bad key for {}", name),
     }
+    Redirect::to(uri!(admin_page))
 }
 
 // TODO move into utils and make more checks.
@@ -67,11 +217,14 @@ pub async fn get_collection(
     encoded: &str,
     blobs: &BlobsProtocol,
     endpoint: &Endpoint,
+    ids: &IdClient,
 ) -> anyhow::Result<()> {
     match BlobTicket::from_str(encoded) {
         Ok(ticket) => {
             println!("{:#?}", ticket);
             let (node, hash, hashtype) = ticket.into_parts();
+            // remember where it was so it can be dialed again
+            ids.seen(node.id, Some(node.clone()), "ticket").await?;
             let conn = endpoint.connect(node, iroh_blobs::ALPN).await?;
             let knf = HashAndFormat::new(hash, hashtype);
             let local = blobs.store().remote().local(knf).await?;
@@ -103,9 +256,10 @@ pub async fn ticket<'r>(
     blobs: &State<BlobsProtocol>,
     endpoint: &State<Endpoint>,
     file_set: &State<FileSet>,
+    ids: &State<IdClient>,
 ) -> &'static str {
     let encoded = web_message.ticket.trim();
-    let r = get_collection(encoded, blobs, endpoint).await;
+    let r = get_collection(encoded, blobs, endpoint, ids).await;
     warn!("{:#?}",r);
     file_set.fill("col").await;
     "blob status"
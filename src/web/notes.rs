@@ -1,15 +1,31 @@
 // Notes web interface
 
-use crate::notes::{self, Notes};
-use crate::templates::{NoteCreateTemplate, NoteEditTemplate, NotePageTemplate, NotesPageTemplate};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::import::{self, ImportReport};
+use crate::markdown::{self, LinkResolver};
+use crate::notes::{self, Download, NoteLink, Notes, Update};
+use crate::publish;
+use crate::store::FileSet;
+use crate::templates::{
+    NoteConflictTemplate, NoteCreateTemplate, NoteEditTemplate, NotePageTemplate,
+    NoteSnapshotTemplate, NotesImportTemplate, NotesPageTemplate, NotesSnapshotsTemplate,
+    NotesTasksTemplate, NotesTrashTemplate,
+};
 use crate::web::auth::User;
+use crate::web::untrusted::Untrusted;
 
 use rocket::State;
 use rocket::fairing::AdHoc;
 use rocket::form::Form;
+use rocket::fs::TempFile;
 use rocket::response::{Redirect, Responder};
+use tokio::io::AsyncReadExt;
+
+use iroh_blobs::{BlobsProtocol, Hash};
 
-use pulldown_cmark::{Options, Parser};
 
 pub fn stage() -> AdHoc {
     AdHoc::on_ignite("Notes Browser", |rocket| async {
@@ -22,20 +38,73 @@ pub fn stage() -> AdHoc {
                 make_note,
                 edit_note,
                 update_note,
-                delete_note
+                rename_note,
+                private_note,
+                set_download,
+                delete_note,
+                show_trash,
+                restore_note,
+                purge_note,
+                show_snapshots,
+                show_snapshot,
+                take_snapshot,
+                restore_snapshot,
+                publish_notes,
+                show_import,
+                import_notes,
+                show_tasks,
+                toggle_task,
+                attach_file,
+                attachment
             ],
         )
     })
 }
 
-#[get("/notes")]
-pub async fn show_notes<'r>(notes: &State<Notes>) -> impl Responder<'r, 'static> {
+#[get("/notes?<tag>")]
+pub async fn show_notes<'r>(tag: Option<&str>, notes: &State<Notes>) -> impl Responder<'r, 'static> {
     // println!("{:#?}", notes.get_note_vec().await);
     // for note in &notes.get_note_vec().await { 
     //     println!("{:#?}",&note);
     // }
+    let listed = match tag {
+        Some(tag) => notes.notes_with_tag(tag).await,
+        None => notes.get_notes().await,
+    };
+    let (note_list, error) = match listed {
+        Ok(listed) => (
+            listed
+                .into_iter()
+                .map(|n| NoteLink {
+                    title: n.title().to_string(),
+                    id: n.id,
+                    locked: n.locked,
+                })
+                .collect(),
+            None,
+        ),
+        Err(e) => {
+            warn!("note list failed {:?}", e);
+            (Vec::new(), Some(format!("Could not list the notes: {e}")))
+        }
+    };
+    let current = match notes.download().await {
+        Ok(download) => download,
+        Err(e) => {
+            warn!("download policy failed {:?}", e);
+            Download::Everything
+        }
+    };
+    let downloads = Download::ALL
+        .iter()
+        .map(|d| (d.name().to_string(), d.label().to_string(), *d == current))
+        .collect();
     NotesPageTemplate {
-        notes: notes.get_note_vec().await,
+        notes: note_list,
+        error: error,
+        downloads: downloads,
+        tags: notes.tag_cloud().await.unwrap_or_default(),
+        tag: tag.map(|t| t.to_string()),
         ticket: Some(notes.ticket()),
         section: "notes".to_string(),
     }
@@ -43,27 +112,19 @@ pub async fn show_notes<'r>(notes: &State<Notes>) -> impl Responder<'r, 'static>
 
 #[get("/notes/show/<doc_id>")]
 pub async fn show_note<'r>(doc_id: &str, notes: &State<Notes>) -> impl Responder<'r, 'static> {
-    let doc_res = notes.get_note(doc_id.to_string()).await;
+    let doc_res = notes.open_note(doc_id.to_string()).await;
     let (value, note) = match doc_res {
-        Ok(doc) => (doc.text.clone(), doc),
+        Ok(doc) => (doc.body().to_string(), doc),
         Err(_) => todo!(),
     };
+    let titles = notes.title_index().await.unwrap_or_default();
 
     // println!("{:#?}",note);
-    // let parser_test = Parser::new_ext(
-    //     &value,
-    //     Options::ENABLE_WIKILINKS | Options::ENABLE_TASKLISTS,
-    // );
-    // for item in parser_test {
-    //     println!("tag {:#?}", item);
-    // }
-    let parser = Parser::new_ext(
-        &value,
-        Options::ENABLE_WIKILINKS | Options::ENABLE_TASKLISTS,
-    );
-
-    let mut md = String::new();
-    pulldown_cmark::html::push_html(&mut md, parser);
+    let links = NoteLinks {
+        id: &note.id,
+        titles: &titles,
+    };
+    let md = markdown::render(&value, &links);
     NotePageTemplate {
         note: note,
         text: md,
@@ -78,23 +139,26 @@ pub async fn edit_note<'r>(
     notes: &State<Notes>,
     _user: User,
 ) -> impl Responder<'r, 'static> {
-    let doc_res = notes.get_note(doc_id.to_string()).await;
-    let note = match doc_res {
-        Ok(doc) => doc,
-        Err(_) => notes::Note::bad_note(),
+    let doc_res = notes.get_note_head(doc_id.to_string()).await;
+    let (note, base) = match doc_res {
+        Ok((doc, head)) => (doc, head),
+        Err(_) => (notes::Note::bad_note(), None),
     };
 
     NoteEditTemplate {
         note: note,
+        base: base.map(|h| h.to_hex().to_string()).unwrap_or_default(),
         section: "notes".to_string(),
         notes: notes.get_note_vec().await,
     }
 }
 
-#[get("/notes/create")]
-pub async fn create_note<'r>(notes: &State<Notes>) -> impl Responder<'r, 'static> {
+// title is filled in when following a wiki link to a missing note
+#[get("/notes/create?<title>")]
+pub async fn create_note<'r>(title: Option<&str>, notes: &State<Notes>) -> impl Responder<'r, 'static> {
     NoteCreateTemplate {
         section: "notes".to_string(),
+        title: title.unwrap_or_default().to_string(),
         title_error: false,
         notes: notes.get_note_vec().await,
         text: "".to_string(),
@@ -113,52 +177,521 @@ pub async fn make_note<'r>(
     note_data: Form<NoteCreate<'_>>,
     notes: &State<Notes>,
 ) -> Result<Redirect, NoteCreateTemplate> {
-    let clean_title: String = note_data
-        .title
-        .chars()
-        .filter(|c| c.is_ascii_alphanumeric() || c.is_whitespace())
-        .collect();
+    let title = note_data.title.trim();
     // No empty titles.
-    if note_data.title == "".to_string() {
+    if title.is_empty() {
         return Err(NoteCreateTemplate {
             section: "notes".to_string(),
+            title: "".to_string(),
             title_error: true,
             notes: notes.get_note_vec().await,
             text: note_data.text.to_string(),
         });
     }
     let res = notes
-        .create(clean_title.clone(), note_data.text.to_string())
+        .create_titled(title.to_string(), note_data.text.to_string())
         .await;
     match res {
-        Ok(_) => return Ok(Redirect::to(uri!(show_note(clean_title)))),
+        Ok(id) => return Ok(Redirect::to(uri!(show_note(id)))),
         Err(e) => {
             println!("{:#?}", e);
-            return Ok(Redirect::to(uri!(create_note())));
+            return Ok(Redirect::to(uri!(create_note(_))));
         }
     }
 }
 
+// Edit form data , base is the content hash the edit started from
+#[derive(FromForm, Debug)]
+pub struct NoteUpdate<'v> {
+    id: &'v str,
+    text: &'v str,
+    base: &'v str,
+}
+
 #[post("/notes/update", data = "<note_data>")]
 pub async fn update_note<'r>(
-    note_data: Form<NoteCreate<'_>>,
+    note_data: Form<NoteUpdate<'_>>,
     notes: &State<Notes>,
-) -> impl Responder<'r, 'static> {
+) -> Result<Redirect, NoteConflictTemplate> {
     // println!("{:?}", note_data);
+    // blank base is a note that did not exist yet
+    let base = Hash::from_str(note_data.base).ok();
     let res = notes
-        .update_note(note_data.title.to_string(), note_data.text.to_string())
+        .update_checked(
+            note_data.id.to_string(),
+            note_data.text.to_string(),
+            base,
+        )
         .await;
     match res {
-        Ok(_) => Redirect::to(uri!(show_note(note_data.title))),
-        Err(_) => Redirect::to(uri!(create_note())),
+        Ok(Update::Saved) => Ok(Redirect::to(uri!(show_note(note_data.id)))),
+        Ok(Update::Conflict(conflict)) => Err(NoteConflictTemplate {
+            head: conflict
+                .head
+                .map(|h| h.to_hex().to_string())
+                .unwrap_or_default(),
+            conflict: conflict,
+            section: "notes".to_string(),
+            notes: notes.get_note_vec().await,
+        }),
+        Err(_) => Ok(Redirect::to(uri!(create_note(_)))),
+    }
+}
+
+#[derive(FromForm, Debug)]
+pub struct NoteRename<'v> {
+    id: &'v str,
+    title: &'v str,
+    rewrite: bool,
+}
+
+#[post("/notes/rename", data = "<rename>")]
+pub async fn rename_note<'r>(
+    rename: Form<NoteRename<'_>>,
+    notes: &State<Notes>,
+    _user: User,
+) -> Option<Redirect> {
+    match notes.find_note(rename.id.to_string()).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return None,
+        Err(e) => {
+            warn!("rename lookup failed {:?}", e);
+            return None;
+        }
+    }
+    let title = rename.title.trim();
+    if title.is_empty() {
+        return Some(Redirect::to(uri!(edit_note(rename.id))));
+    }
+    match notes
+        .rename(rename.id.to_string(), title.to_string(), rename.rewrite)
+        .await
+    {
+        Ok(count) => info!("renamed {} to {} , {} notes relinked", rename.id, title, count),
+        Err(e) => warn!("rename failed {:?}", e),
+    }
+    Some(Redirect::to(uri!(show_note(rename.id))))
+}
+
+#[derive(FromForm, Debug)]
+pub struct NotePrivate<'v> {
+    id: &'v str,
+    private: bool,
+}
+
+#[post("/notes/private", data = "<private>")]
+pub async fn private_note<'r>(
+    private: Form<NotePrivate<'_>>,
+    notes: &State<Notes>,
+    _user: User,
+) -> Redirect {
+    if let Err(e) = notes
+        .set_private(private.id.to_string(), private.private)
+        .await
+    {
+        warn!("private change failed {:?}", e);
+    }
+    Redirect::to(uri!(show_note(private.id)))
+}
+
+#[derive(FromForm, Debug)]
+pub struct DownloadForm<'v> {
+    download: &'v str,
+}
+
+#[post("/notes/download", data = "<form>")]
+pub async fn set_download<'r>(
+    form: Form<DownloadForm<'_>>,
+    notes: &State<Notes>,
+    _user: User,
+) -> Redirect {
+    match Download::from_name(form.download) {
+        Some(download) => match notes.set_download(download).await {
+            Ok(_) => info!("notes download policy now {}", download.name()),
+            Err(e) => warn!("download policy change failed {:?}", e),
+        },
+        None => warn!("unknown download policy {}", form.download),
     }
+    Redirect::to(uri!(show_notes(_)))
 }
 
 #[get("/notes/delete/<doc_id>")]
-pub async fn delete_note<'r>(doc_id: &str, notes: &State<Notes>) -> impl Responder<'r, 'static> {
-    let res = notes.set_delete(doc_id.to_string()).await;
+pub async fn delete_note<'r>(
+    doc_id: &str,
+    notes: &State<Notes>,
+    user: User,
+) -> impl Responder<'r, 'static> {
+    let res = notes.set_delete(doc_id.to_string(), &user.name).await;
     match res {
-        Ok(_) => Redirect::to(uri!(show_notes)),
-        Err(_) => Redirect::to(uri!(show_notes)),
+        Ok(_) => Redirect::to(uri!(show_notes(_))),
+        Err(_) => Redirect::to(uri!(show_notes(_))),
+    }
+}
+
+#[get("/notes/trash")]
+pub async fn show_trash<'r>(notes: &State<Notes>, _user: User) -> impl Responder<'r, 'static> {
+    let trash = match notes.get_trash().await {
+        Ok(trash) => trash,
+        Err(e) => {
+            warn!("trash listing failed {:?}", e);
+            vec![]
+        }
+    };
+    NotesTrashTemplate {
+        trash: trash,
+        trash_days: notes.trash_days(),
+        section: "notes".to_string(),
+        notes: notes.get_note_vec().await,
+    }
+}
+
+#[get("/notes/restore/<doc_id>")]
+pub async fn restore_note<'r>(doc_id: &str, notes: &State<Notes>, _user: User) -> Redirect {
+    if let Err(e) = notes.restore(doc_id.to_string()).await {
+        warn!("restore failed {:?}", e);
+        return Redirect::to(uri!(show_trash));
+    }
+    Redirect::to(uri!(show_note(doc_id)))
+}
+
+#[get("/notes/purge/<doc_id>")]
+pub async fn purge_note<'r>(doc_id: &str, notes: &State<Notes>, _user: User) -> Redirect {
+    if let Err(e) = notes.purge(doc_id.to_string()).await {
+        warn!("purge failed {:?}", e);
+    }
+    Redirect::to(uri!(show_trash))
+}
+
+#[get("/notes/snapshots")]
+pub async fn show_snapshots<'r>(notes: &State<Notes>, _user: User) -> impl Responder<'r, 'static> {
+    let snapshots = match notes.list_snapshots().await {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            warn!("snapshot listing failed {:?}", e);
+            vec![]
+        }
+    };
+    NotesSnapshotsTemplate {
+        snapshots: snapshots,
+        section: "notes".to_string(),
+        notes: notes.get_note_vec().await,
+    }
+}
+
+#[post("/notes/snapshots")]
+pub async fn take_snapshot<'r>(notes: &State<Notes>, _user: User) -> Redirect {
+    match notes.bounce_down().await {
+        Ok(Some(tag)) => info!("notes snapshot {}", tag),
+        Ok(None) => info!("notes unchanged , no snapshot"),
+        Err(e) => warn!("snapshot failed {:?}", e),
+    }
+    Redirect::to(uri!(show_snapshots))
+}
+
+#[get("/notes/snapshots/<tag>")]
+pub async fn show_snapshot<'r>(
+    tag: &str,
+    notes: &State<Notes>,
+    _user: User,
+) -> Result<NoteSnapshotTemplate, Redirect> {
+    match notes.snapshot_notes(tag).await {
+        Ok(items) => Ok(NoteSnapshotTemplate {
+            tag: tag.to_string(),
+            items: items,
+            section: "notes".to_string(),
+            notes: notes.get_note_vec().await,
+        }),
+        Err(e) => {
+            warn!("snapshot {} failed {:?}", tag, e);
+            Err(Redirect::to(uri!(show_snapshots)))
+        }
+    }
+}
+
+// Restore picker , nothing ticked and all set restores everything
+#[derive(FromForm, Debug)]
+pub struct SnapshotRestore {
+    all: bool,
+    ids: Vec<String>,
+}
+
+#[post("/notes/snapshots/<tag>", data = "<restore>")]
+pub async fn restore_snapshot<'r>(
+    tag: &str,
+    restore: Form<SnapshotRestore>,
+    notes: &State<Notes>,
+    _user: User,
+) -> Redirect {
+    let only = match restore.all {
+        true => None,
+        false => Some(restore.ids.as_slice()),
+    };
+    match notes.bounce_up(tag, only).await {
+        Ok(count) => info!("restored {} notes from {}", count, tag),
+        Err(e) => warn!("restore from {} failed {:?}", tag, e),
+    }
+    Redirect::to(uri!(show_notes(_)))
+}
+
+#[get("/notes/tasks")]
+pub async fn show_tasks<'r>(notes: &State<Notes>) -> impl Responder<'r, 'static> {
+    let tasks = match notes.tasks().await {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            warn!("task listing failed {:?}", e);
+            vec![]
+        }
+    };
+    let tasks = tasks
+        .into_iter()
+        .map(|(link, task)| {
+            let checkbox = task_checkbox(&link.id, task.line, task.checked, true);
+            (link, task, checkbox)
+        })
+        .collect();
+    NotesTasksTemplate {
+        tasks: tasks,
+        section: "notes".to_string(),
+        notes: notes.get_note_vec().await,
+    }
+}
+
+#[derive(FromForm, Debug)]
+pub struct TaskToggle<'v> {
+    id: &'v str,
+    line: usize,
+    checked: bool,
+    to_list: bool,
+}
+
+#[post("/notes/tasks/toggle", data = "<toggle>")]
+pub async fn toggle_task<'r>(
+    toggle: Form<TaskToggle<'_>>,
+    notes: &State<Notes>,
+    _user: User,
+) -> Redirect {
+    if let Err(e) = notes
+        .toggle_task(toggle.id.to_string(), toggle.line, toggle.checked)
+        .await
+    {
+        warn!("task toggle failed {:?}", e);
+    }
+    match toggle.to_list {
+        true => Redirect::to(uri!(show_tasks)),
+        false => Redirect::to(uri!(show_note(toggle.id))),
+    }
+}
+
+// Render the notebook to a static site collection and show it in the file browser
+#[post("/notes/publish")]
+pub async fn publish_notes<'r>(
+    notes: &State<Notes>,
+    blobs: &State<BlobsProtocol>,
+    file_set: &State<FileSet>,
+    _user: User,
+) -> Redirect {
+    match publish::publish(notes, blobs).await {
+        Ok((tag, hash)) => {
+            info!("published {} as {}", hash, tag);
+            file_set.fill(publish::SITE_PREFIX).await;
+            Redirect::to(format!("/files/{tag}"))
+        }
+        Err(e) => {
+            warn!("publish failed {:?}", e);
+            Redirect::to(uri!(show_notes(_)))
+        }
+    }
+}
+
+// web.import_root from the config , directory imports stay inside it
+pub struct ImportRoot(pub Option<PathBuf>);
+
+impl ImportRoot {
+    fn shown(&self) -> Option<String> {
+        self.0.as_ref().map(|root| root.display().to_string())
+    }
+}
+
+#[get("/notes/import")]
+pub async fn show_import<'r>(
+    notes: &State<Notes>,
+    file_set: &State<FileSet>,
+    import_root: &State<ImportRoot>,
+    _user: User,
+) -> impl Responder<'r, 'static> {
+    NotesImportTemplate {
+        collections: file_set.list_roots(),
+        import_root: import_root.shown(),
+        report: None,
+        error: None,
+        section: "notes".to_string(),
+        notes: notes.get_note_vec().await,
+    }
+}
+
+// A directory on this node or one of the collections , directory wins
+#[derive(FromForm, Debug)]
+pub struct ImportSource<'v> {
+    dir: &'v str,
+    collection: &'v str,
+}
+
+#[post("/notes/import", data = "<source>")]
+pub async fn import_notes<'r>(
+    source: Form<ImportSource<'_>>,
+    notes: &State<Notes>,
+    blobs: &State<BlobsProtocol>,
+    file_set: &State<FileSet>,
+    import_root: &State<ImportRoot>,
+    _user: User,
+) -> impl Responder<'r, 'static> {
+    let res = run_import(&source, notes, blobs, file_set, import_root).await;
+    let (report, error) = match res {
+        Ok(report) => {
+            info!(
+                "imported {} notes , {} failed",
+                report.imported.len(),
+                report.failed.len()
+            );
+            (Some(report), None)
+        }
+        Err(e) => {
+            warn!("import failed {:?}", e);
+            (None, Some(e.to_string()))
+        }
+    };
+    NotesImportTemplate {
+        collections: file_set.list_roots(),
+        import_root: import_root.shown(),
+        report: report,
+        error: error,
+        section: "notes".to_string(),
+        notes: notes.get_note_vec().await,
+    }
+}
+
+async fn run_import(
+    source: &ImportSource<'_>,
+    notes: &Notes,
+    blobs: &BlobsProtocol,
+    file_set: &FileSet,
+    import_root: &ImportRoot,
+) -> anyhow::Result<ImportReport> {
+    let dir = source.dir.trim();
+    if !dir.is_empty() {
+        let Some(root) = &import_root.0 else {
+            anyhow::bail!("directory imports are off , set web.import_root in the config");
+        };
+        let dir = import::resolve_dir(root, dir)?;
+        return import::import_dir(notes, &dir).await;
+    }
+    match file_set.get_hash(source.collection.to_string()).await? {
+        Some(hash) => import::import_collection(notes, blobs, hash).await,
+        None => anyhow::bail!("no collection called {}", source.collection),
+    }
+}
+
+// Attachments through the attachment route
+// and wiki links to the note id with that title.
+struct NoteLinks<'a> {
+    id: &'a str,
+    titles: &'a HashMap<String, String>,
+}
+
+impl LinkResolver for NoteLinks<'_> {
+    fn attachment(&self, hash: &str) -> String {
+        format!("/notes/attachment/{}/{}", self.id, hash)
+    }
+
+    fn wiki(&self, target: &str) -> String {
+        let target = markdown::wiki_target(target);
+        match self.titles.get(&target.to_lowercase()) {
+            Some(id) => uri!(show_note(id)).to_string(),
+            None => uri!(create_note(Some(target))).to_string(),
+        }
     }
+
+    fn task(&self, line: usize, checked: bool) -> Option<String> {
+        Some(task_checkbox(self.id, line, checked, false))
+    }
+}
+
+// Clickable checkbox , static/js/tasks.js posts it to the toggle route.
+// Only data-task-* attributes make it through the sanitiser.
+fn task_checkbox(id: &str, line: usize, checked: bool, to_list: bool) -> String {
+    format!(
+        r#"<input type="checkbox" class="task" data-task-note="{}" data-task-line="{}" data-task-checked="{}" data-task-list="{}"{}>"#,
+        id,
+        line,
+        checked,
+        to_list,
+        if checked { " checked" } else { "" }
+    )
+}
+
+// Attachment upload from the edit page
+#[derive(FromForm)]
+pub struct AttachUpload<'v> {
+    file: TempFile<'v>,
+}
+
+#[post("/notes/attach/<doc_id>", data = "<upload>")]
+pub async fn attach_file<'r>(
+    doc_id: &str,
+    upload: Form<AttachUpload<'_>>,
+    notes: &State<Notes>,
+    _user: User,
+) -> Option<Redirect> {
+    // a made up id would otherwise turn into a new note
+    match notes.find_note(doc_id.to_string()).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return None,
+        Err(e) => {
+            warn!("attach lookup failed {:?}", e);
+            return None;
+        }
+    }
+    // keep the extension , it drives the content type on the way out
+    let name = match upload.file.raw_name() {
+        Some(name) => name
+            .dangerous_unsafe_unsanitized_raw()
+            .as_str()
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or("attachment")
+            .to_string(),
+        None => "attachment".to_string(),
+    };
+    let mut data = Vec::new();
+    let res = match upload.file.open().await {
+        Ok(file) => {
+            tokio::pin!(file);
+            file.read_to_end(&mut data).await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        warn!("attachment upload failed {:?}", e);
+        return Some(Redirect::to(uri!(edit_note(doc_id))));
+    }
+    match notes.attach(doc_id.to_string(), name, data.into()).await {
+        Ok(attachment) => info!("attached {} to {}", attachment.hash, doc_id),
+        Err(e) => warn!("attach failed {:?}", e),
+    }
+    Some(Redirect::to(uri!(edit_note(doc_id))))
+}
+
+#[get("/notes/attachment/<doc_id>/<hash>")]
+pub async fn attachment(
+    doc_id: &str,
+    hash: &str,
+    notes: &State<Notes>,
+    _user: User,
+) -> Option<Untrusted> {
+    let hash = Hash::from_str(hash).ok()?;
+    let (attachment, data) = notes
+        .get_attachment(doc_id.to_string(), hash)
+        .await
+        .ok()??;
+    Some(Untrusted::new(&attachment.name, data.to_vec()))
 }
@@ -0,0 +1,94 @@
+//! Files that came from peers.
+//! Attachments and collection files are whatever someone else synced , so
+//! they never get to run as a page on this origin next to the session
+//! cookie. Everything goes out sandboxed and unsniffed , and anything
+//! that isn't a plain raster image is a download.
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+use rocket::http::{ContentType, Header};
+
+// shown in the page , the rest downloads
+const INLINE: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+#[derive(Responder)]
+pub struct Untrusted {
+    data: Vec<u8>,
+    content_type: ContentType,
+    disposition: Header<'static>,
+    csp: Header<'static>,
+    nosniff: Header<'static>,
+}
+
+impl Untrusted {
+    pub fn new(name: &str, data: Vec<u8>) -> Self {
+        let extension = Path::new(name)
+            .extension()
+            .and_then(OsStr::to_str)
+            .map(str::to_lowercase);
+        let content_type = extension
+            .as_deref()
+            .and_then(ContentType::from_extension)
+            .unwrap_or(ContentType::Bytes);
+        let disposition = match extension.as_deref() {
+            Some(ext) if INLINE.contains(&ext) => "inline".to_string(),
+            _ => format!("attachment; filename=\"{}\"", file_name(name)),
+        };
+        Self {
+            data,
+            content_type,
+            disposition: Header::new("Content-Disposition", disposition),
+            csp: Header::new("Content-Security-Policy", "sandbox"),
+            nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+        }
+    }
+}
+
+// last path part , safe inside the quotes
+fn file_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let clean: String = name
+        .chars()
+        .map(|c| match c.is_ascii_graphic() || c == ' ' {
+            true if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    match clean.is_empty() {
+        true => "download".to_string(),
+        false => clean,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disposition(name: &str) -> String {
+        Untrusted::new(name, vec![]).disposition.value().to_string()
+    }
+
+    #[test]
+    fn images_inline() {
+        assert_eq!(disposition("cat.png"), "inline");
+        assert_eq!(disposition("cat.JPEG"), "inline");
+        assert_eq!(disposition("cat.webp"), "inline");
+    }
+
+    #[test]
+    fn markup_downloads() {
+        assert_eq!(disposition("x.html"), "attachment; filename=\"x.html\"");
+        assert_eq!(disposition("x.svg"), "attachment; filename=\"x.svg\"");
+        assert_eq!(disposition("dir/x.xml"), "attachment; filename=\"x.xml\"");
+        assert_eq!(disposition("noext"), "attachment; filename=\"noext\"");
+    }
+
+    #[test]
+    fn names_are_quoted_safely() {
+        assert_eq!(file_name("a\"b\\c.txt"), "c.txt");
+        assert_eq!(file_name("a\"b.txt"), "a_b.txt");
+        assert_eq!(file_name("ünï.txt"), "_n_.txt");
+        assert_eq!(file_name(""), "download");
+    }
+}
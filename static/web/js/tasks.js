@@ -0,0 +1,21 @@
+// Task checkboxes in notes and the task list.
+// Post the toggle and reload so the page shows the saved markdown.
+
+document.addEventListener("change", (e) => {
+    const box = e.target;
+    if (!box.matches("input.task")) {
+        return;
+    }
+    box.disabled = true;
+    fetch("/notes/tasks/toggle", {
+        method: "POST",
+        body: new URLSearchParams({
+            id: box.dataset.taskNote,
+            line: box.dataset.taskLine,
+            checked: box.dataset.taskChecked,
+            to_list: box.dataset.taskList,
+        }),
+    }).then(() => {
+        window.location.reload();
+    });
+});